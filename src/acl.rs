@@ -0,0 +1,414 @@
+//! Access control lists in the edge graph.
+//!
+//! The owner of a file has every right on it. Other users get rights through grants, each a
+//! `root->acl` node naming the owner, the blob key of the file, a grantee and its rights. A
+//! grantee is an email or `group:{name}`, where groups are `root->group_member` nodes.
+//!
+//! Grants are indexed by key and by grantee, memberships by group and by email.
+use std::{fmt::Display, io, str::FromStr, sync::Arc};
+
+use edge_lib::{data::AsDataManager, Path};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    graph::{self, execute, get_field, Journal},
+    util,
+};
+
+const GROUP_PREFIX: &str = "group:";
+
+async fn load_all(
+    dm: Arc<dyn AsDataManager>,
+    node_v: Vec<String>,
+) -> io::Result<Vec<(String, Grant)>> {
+    let mut grant_v = Vec::with_capacity(node_v.len());
+    for node in node_v {
+        let grant = Grant::load(dm.as_ref(), &node).await?;
+        grant_v.push((node, grant));
+    }
+    Ok(grant_v)
+}
+
+/// Script writing membership of `email` in `group` as `root->group_member` node `node`, with its
+/// indexes.
+fn membership_script(node: &str, group: &str, email: &str) -> String {
+    [
+        &format!("$->$group_member = = {node} _"),
+        &format!("$->$group_member->group = = {} _", util::escape(group)),
+        &format!("$->$group_member->email = = {} _", util::escape(email)),
+        "root->group_member += = $->$group_member _",
+        &graph::index_line_v("group_member_by_group", group, "group_member").join("\n"),
+        &graph::index_line_v("group_member_by_email", email, "group_member").join("\n"),
+    ]
+    .join("\n")
+}
+
+/// Load memberships `node_v` as node, group and email.
+async fn load_memberships(
+    dm: Arc<dyn AsDataManager>,
+    node_v: Vec<String>,
+) -> io::Result<Vec<(String, String, String)>> {
+    let mut member_v = Vec::with_capacity(node_v.len());
+    for node in node_v {
+        let group = util::unescape(&get_field(dm.as_ref(), &node, "group").await?);
+        let email = util::unescape(&get_field(dm.as_ref(), &node, "email").await?);
+        member_v.push((node, group, email));
+    }
+    Ok(member_v)
+}
+
+// Public
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Right {
+    /// Download and stat the file.
+    Read,
+    /// Rename the file.
+    Write,
+    /// Delete the file, for every grantee.
+    Delete,
+}
+
+impl Display for Right {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Right::Read => write!(f, "read"),
+            Right::Write => write!(f, "write"),
+            Right::Delete => write!(f, "delete"),
+        }
+    }
+}
+
+impl FromStr for Right {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(Right::Read),
+            "write" => Ok(Right::Write),
+            "delete" => Ok(Right::Delete),
+            _ => Err(io::Error::other(format!("unknown right: {s}"))),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Grant {
+    pub owner: String,
+    /// Blob key of the file, see `FileRecord::key`.
+    pub key: String,
+    /// An email or `group:{name}`.
+    pub grantee: String,
+    pub right_v: Vec<Right>,
+    pub created_at: u64,
+}
+
+impl Grant {
+    /// Load the grant of node `node`.
+    pub async fn load(dm: &dyn AsDataManager, node: &str) -> io::Result<Self> {
+        Ok(Self {
+            owner: util::unescape(&get_field(dm, node, "owner").await?),
+            key: get_field(dm, node, "key").await?,
+            grantee: util::unescape(&get_field(dm, node, "grantee").await?),
+            right_v: get_field(dm, node, "rights")
+                .await?
+                .split(',')
+                .filter_map(|right| right.parse().ok())
+                .collect(),
+            created_at: get_field(dm, node, "created_at")
+                .await?
+                .parse()
+                .unwrap_or(0),
+        })
+    }
+
+    /// Write this grant into the graph as a new `root->acl` node.
+    pub async fn insert(&self, dm: Arc<dyn AsDataManager>, journal: &Journal) -> io::Result<()> {
+        let script = self.script(&graph::new_node());
+        execute(dm, journal, script, "insert acl").await
+    }
+
+    /// Script writing this grant as `root->acl` node `node`, with its indexes.
+    fn script(&self, node: &str) -> String {
+        let right_v: Vec<String> = self.right_v.iter().map(|right| right.to_string()).collect();
+        [
+            &format!("$->$acl = = {node} _"),
+            &format!("$->$acl->owner = = {} _", util::escape(&self.owner)),
+            &format!("$->$acl->key = = {} _", self.key),
+            &format!("$->$acl->grantee = = {} _", util::escape(&self.grantee)),
+            &format!("$->$acl->rights = = {} _", right_v.join(",")),
+            &format!("$->$acl->created_at = = {} _", self.created_at),
+            "root->acl += = $->$acl _",
+            &graph::index_line_v("acl_by_key", &self.key, "acl").join("\n"),
+            &graph::index_line_v("acl_by_grantee", &self.grantee, "acl").join("\n"),
+        ]
+        .join("\n")
+    }
+
+    /// Whether this grant gives `right` to `email` in `group_v`.
+    pub fn allows(&self, email: &str, group_v: &[String], right: Right) -> bool {
+        let is_grantee = match self.grantee.strip_prefix(GROUP_PREFIX) {
+            Some(group) => group_v.iter().any(|g| g == group),
+            None => self.grantee == email,
+        };
+        is_grantee && self.right_v.contains(&right)
+    }
+}
+
+/// Whether `grantee` is an email or `group:{name}`.
+pub fn is_grantee(grantee: &str) -> bool {
+    match grantee.strip_prefix(GROUP_PREFIX) {
+        Some(group) => !group.is_empty(),
+        None => grantee.contains('@'),
+    }
+}
+
+/// List all grants with their nodes.
+pub async fn list(dm: Arc<dyn AsDataManager>) -> io::Result<Vec<(String, Grant)>> {
+    let node_v = dm.get(&Path::from_str("root->acl")).await?;
+    load_all(dm, node_v).await
+}
+
+/// List the grants on blob key `key`, of any owner, with their nodes.
+pub async fn list_by_key(
+    dm: Arc<dyn AsDataManager>,
+    key: &str,
+) -> io::Result<Vec<(String, Grant)>> {
+    let node_v = graph::lookup(dm.as_ref(), "acl_by_key", key).await?;
+    load_all(dm, node_v).await
+}
+
+/// List the grants to `grantee`, an email or `group:{name}`, with their nodes.
+pub async fn list_by_grantee(
+    dm: Arc<dyn AsDataManager>,
+    grantee: &str,
+) -> io::Result<Vec<(String, Grant)>> {
+    let node_v = graph::lookup(dm.as_ref(), "acl_by_grantee", grantee).await?;
+    load_all(dm, node_v).await
+}
+
+/// List the grants to `email` or to any of its groups, with their nodes.
+pub async fn list_for(dm: Arc<dyn AsDataManager>, email: &str) -> io::Result<Vec<(String, Grant)>> {
+    let mut grant_v = list_by_grantee(dm.clone(), email).await?;
+    for group in groups_of(dm.clone(), email).await? {
+        grant_v.extend(list_by_grantee(dm.clone(), &format!("{GROUP_PREFIX}{group}")).await?);
+    }
+    Ok(grant_v)
+}
+
+/// Remove grant node `node` from `root->acl` and its indexes.
+async fn remove(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    node: &str,
+    grant: &Grant,
+) -> io::Result<()> {
+    let index_v = [
+        ("acl_by_key", grant.key.as_str()),
+        ("acl_by_grantee", grant.grantee.as_str()),
+    ];
+    graph::remove_node(dm, journal, "acl", node, &index_v).await
+}
+
+/// Grant `right_v` on file `key` of `owner` to `grantee`, replacing its former rights.
+pub async fn grant(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    owner: &str,
+    key: &str,
+    grantee: &str,
+    right_v: Vec<Right>,
+) -> io::Result<Grant> {
+    revoke(dm.clone(), journal, owner, key, Some(grantee)).await?;
+    let grant = Grant {
+        owner: owner.to_string(),
+        key: key.to_string(),
+        grantee: grantee.to_string(),
+        right_v,
+        created_at: util::timestamp(),
+    };
+    grant.insert(dm, journal).await?;
+    Ok(grant)
+}
+
+/// Revoke the grants on file `key` of `owner` to `grantee`, or to anyone if `None`, returning
+/// how many were revoked.
+pub async fn revoke(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    owner: &str,
+    key: &str,
+    grantee: Option<&str>,
+) -> io::Result<usize> {
+    let mut count = 0;
+    for (node, grant) in list_by_key(dm.clone(), key).await? {
+        if grant.owner == owner && grantee.is_none_or(|grantee| grant.grantee == grantee) {
+            remove(dm.clone(), journal, &node, &grant).await?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// List the members of group `group`.
+pub async fn list_members(dm: Arc<dyn AsDataManager>, group: &str) -> io::Result<Vec<String>> {
+    let node_v = graph::lookup(dm.as_ref(), "group_member_by_group", group).await?;
+    Ok(load_memberships(dm, node_v)
+        .await?
+        .into_iter()
+        .map(|(_, _, email)| email)
+        .collect())
+}
+
+/// List the groups of `email`.
+pub async fn groups_of(dm: Arc<dyn AsDataManager>, email: &str) -> io::Result<Vec<String>> {
+    let node_v = graph::lookup(dm.as_ref(), "group_member_by_email", email).await?;
+    Ok(load_memberships(dm, node_v)
+        .await?
+        .into_iter()
+        .map(|(_, group, _)| group)
+        .collect())
+}
+
+/// Add `email` to group `group` unless it is a member already.
+pub async fn add_member(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    group: &str,
+    email: &str,
+) -> io::Result<()> {
+    if list_members(dm.clone(), group)
+        .await?
+        .iter()
+        .any(|e| e == email)
+    {
+        return Ok(());
+    }
+    let script = membership_script(&graph::new_node(), group, email);
+    execute(dm, journal, script, "insert group_member").await
+}
+
+/// Remove `email` from group `group`, returning whether it was a member.
+pub async fn remove_member(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    group: &str,
+    email: &str,
+) -> io::Result<bool> {
+    let mut is_found = false;
+    let node_v = graph::lookup(dm.as_ref(), "group_member_by_group", group).await?;
+    for (node, _, e) in load_memberships(dm.clone(), node_v).await? {
+        if e == email {
+            let index_v = [
+                ("group_member_by_group", group),
+                ("group_member_by_email", email),
+            ];
+            graph::remove_node(dm.clone(), journal, "group_member", &node, &index_v).await?;
+            is_found = true;
+        }
+    }
+    Ok(is_found)
+}
+
+/// Scripts recreating every grant and membership on its node.
+pub async fn snapshot(dm: Arc<dyn AsDataManager>) -> io::Result<Vec<String>> {
+    let mut script_v: Vec<String> = list(dm.clone())
+        .await?
+        .into_iter()
+        .map(|(node, grant)| grant.script(&node))
+        .collect();
+    let node_v = dm.get(&Path::from_str("root->group_member")).await?;
+    for (node, group, email) in load_memberships(dm, node_v).await? {
+        script_v.push(membership_script(&node, &group, &email));
+    }
+    Ok(script_v)
+}
+
+#[cfg(test)]
+mod tests {
+    use edge_lib::data::{AsDataManager, MemDataManager};
+
+    use crate::graph::Journal;
+
+    use super::{
+        add_member, grant, groups_of, is_grantee, list, list_by_grantee, list_members,
+        remove_member, revoke, Grant, Right,
+    };
+
+    #[test]
+    fn test_allows() {
+        let grant = Grant {
+            owner: "a@b.c".to_string(),
+            key: "key".to_string(),
+            grantee: "group:team".to_string(),
+            right_v: vec![Right::Read],
+            created_at: 0,
+        };
+        assert!(grant.allows("b@b.c", &["team".to_string()], Right::Read));
+        assert!(!grant.allows("b@b.c", &["team".to_string()], Right::Delete));
+        assert!(!grant.allows("b@b.c", &[], Right::Read));
+        assert!(is_grantee("group:team"));
+        assert!(!is_grantee("group:"));
+        assert!(!is_grantee("team"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test() {
+        let dm = MemDataManager::new();
+        let journal = Journal::new();
+        grant(
+            dm.divide(),
+            &journal,
+            "a@b.c",
+            "key",
+            "b@b.c",
+            vec![Right::Read],
+        )
+        .await
+        .unwrap();
+        grant(
+            dm.divide(),
+            &journal,
+            "a@b.c",
+            "key",
+            "b@b.c",
+            vec![Right::Read, Right::Write],
+        )
+        .await
+        .unwrap();
+        let grant_v = list(dm.divide()).await.unwrap();
+        assert_eq!(grant_v.len(), 1);
+        assert_eq!(grant_v[0].1.right_v, vec![Right::Read, Right::Write]);
+        assert_eq!(
+            list_by_grantee(dm.divide(), "b@b.c").await.unwrap().len(),
+            1
+        );
+
+        add_member(dm.divide(), &journal, "team", "c@b.c")
+            .await
+            .unwrap();
+        add_member(dm.divide(), &journal, "team", "c@b.c")
+            .await
+            .unwrap();
+        assert_eq!(
+            groups_of(dm.divide(), "c@b.c").await.unwrap(),
+            vec!["team".to_string()]
+        );
+        assert!(remove_member(dm.divide(), &journal, "team", "c@b.c")
+            .await
+            .unwrap());
+        assert!(list_members(dm.divide(), "team").await.unwrap().is_empty());
+
+        assert_eq!(
+            revoke(dm.divide(), &journal, "a@b.c", "key", None)
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(list(dm.divide()).await.unwrap().is_empty());
+        assert!(list_by_grantee(dm.divide(), "b@b.c")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
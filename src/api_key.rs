@@ -0,0 +1,200 @@
+//! Long-lived API keys in the edge graph.
+//!
+//! A key is presented as `{id}.{secret}`. Every key is a `root->api_key` node that stores its id,
+//! its owner and the sha256 of its secret; the secret itself is only shown once, when the key is
+//! created. Keys are indexed by id and by owner.
+use std::{io, sync::Arc};
+
+use edge_lib::{data::AsDataManager, Path};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    graph::{self, get_field, Journal},
+    util,
+};
+
+pub(crate) fn hash_secret(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+/// Compare in time independent of where `a` and `b` differ.
+pub(crate) fn is_equal(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+async fn load_all(
+    dm: Arc<dyn AsDataManager>,
+    node_v: Vec<String>,
+) -> io::Result<Vec<(String, ApiKeyRecord)>> {
+    let mut key_v = Vec::with_capacity(node_v.len());
+    for node in node_v {
+        let record = ApiKeyRecord::load(dm.as_ref(), &node).await?;
+        key_v.push((node, record));
+    }
+    Ok(key_v)
+}
+
+// Public
+#[derive(Debug, Clone)]
+pub struct ApiKeyRecord {
+    pub id: String,
+    /// sha256 of the secret as lowercase hex.
+    pub secret_hash: String,
+    pub owner: String,
+    pub created_at: u64,
+}
+
+impl ApiKeyRecord {
+    /// Generate a key for `owner`, returning its record and the key to hand out.
+    pub fn generate(owner: &str) -> (Self, String) {
+        let id = util::byte_v2hex(&rand::random::<[u8; 8]>());
+        let secret = util::byte_v2hex(&rand::random::<[u8; 32]>());
+        let record = Self {
+            id: id.clone(),
+            secret_hash: hash_secret(&secret),
+            owner: owner.to_string(),
+            created_at: util::timestamp(),
+        };
+        (record, format!("{id}.{secret}"))
+    }
+
+    /// Load the record of key node `node`.
+    pub async fn load(dm: &dyn AsDataManager, node: &str) -> io::Result<Self> {
+        Ok(Self {
+            id: get_field(dm, node, "id").await?,
+            secret_hash: get_field(dm, node, "secret_hash").await?,
+            owner: util::unescape(&get_field(dm, node, "owner").await?),
+            created_at: get_field(dm, node, "created_at")
+                .await?
+                .parse()
+                .unwrap_or(0),
+        })
+    }
+
+    /// Write this record into the graph as a new `root->api_key` node.
+    pub async fn insert(&self, dm: Arc<dyn AsDataManager>, journal: &Journal) -> io::Result<()> {
+        let script = self.script(&graph::new_node());
+        graph::execute(dm, journal, script, "insert api_key").await
+    }
+
+    /// Script writing this record as `root->api_key` node `node`, with its indexes.
+    fn script(&self, node: &str) -> String {
+        [
+            &format!("$->$api_key = = {node} _"),
+            &format!("$->$api_key->id = = {} _", self.id),
+            &format!("$->$api_key->secret_hash = = {} _", self.secret_hash),
+            &format!("$->$api_key->owner = = {} _", util::escape(&self.owner)),
+            &format!("$->$api_key->created_at = = {} _", self.created_at),
+            "root->api_key += = $->$api_key _",
+            &graph::index_line_v("api_key_by_id", &self.id, "api_key").join("\n"),
+            &graph::index_line_v("api_key_by_owner", &self.owner, "api_key").join("\n"),
+        ]
+        .join("\n")
+    }
+}
+
+/// List all key records with their nodes.
+pub async fn list(dm: Arc<dyn AsDataManager>) -> io::Result<Vec<(String, ApiKeyRecord)>> {
+    let node_v = dm.get(&Path::from_str("root->api_key")).await?;
+    load_all(dm, node_v).await
+}
+
+/// List the key records of `owner` with their nodes.
+pub async fn list_by_owner(
+    dm: Arc<dyn AsDataManager>,
+    owner: &str,
+) -> io::Result<Vec<(String, ApiKeyRecord)>> {
+    let node_v = graph::lookup(dm.as_ref(), "api_key_by_owner", owner).await?;
+    load_all(dm, node_v).await
+}
+
+/// Find the key with id `id`, with its node.
+pub async fn find(
+    dm: Arc<dyn AsDataManager>,
+    id: &str,
+) -> io::Result<Option<(String, ApiKeyRecord)>> {
+    let node_v = graph::lookup(dm.as_ref(), "api_key_by_id", id).await?;
+    Ok(load_all(dm, node_v).await?.into_iter().next())
+}
+
+/// Get the owner of `key`, or `None` if it is not a valid key.
+pub async fn verify(dm: Arc<dyn AsDataManager>, key: &str) -> io::Result<Option<String>> {
+    let (id, secret) = match key.trim().split_once('.') {
+        Some(r) => r,
+        None => return Ok(None),
+    };
+    let secret_hash = hash_secret(secret);
+    Ok(find(dm, id)
+        .await?
+        .filter(|(_, record)| is_equal(&record.secret_hash, &secret_hash))
+        .map(|(_, record)| record.owner))
+}
+
+/// Remove key node `node` from `root->api_key` and its indexes.
+pub async fn remove(dm: Arc<dyn AsDataManager>, journal: &Journal, node: &str) -> io::Result<()> {
+    let record = ApiKeyRecord::load(dm.as_ref(), node).await?;
+    let index_v = [
+        ("api_key_by_id", record.id.as_str()),
+        ("api_key_by_owner", record.owner.as_str()),
+    ];
+    graph::remove_node(dm, journal, "api_key", node, &index_v).await
+}
+
+/// Scripts recreating every key record on its node.
+pub async fn snapshot(dm: Arc<dyn AsDataManager>) -> io::Result<Vec<String>> {
+    Ok(list(dm)
+        .await?
+        .into_iter()
+        .map(|(node, record)| record.script(&node))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use edge_lib::data::{AsDataManager, MemDataManager};
+
+    use crate::graph::Journal;
+
+    use super::{is_equal, list_by_owner, remove, verify, ApiKeyRecord};
+
+    #[test]
+    fn test_is_equal() {
+        assert!(is_equal("abc", "abc"));
+        assert!(!is_equal("abc", "abd"));
+        assert!(!is_equal("abc", "ab"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test() {
+        let dm = MemDataManager::new();
+        let journal = Journal::new();
+        let (record, key) = ApiKeyRecord::generate("a@b.c");
+        assert!(key.starts_with(&format!("{}.", record.id)));
+        record.insert(dm.divide(), &journal).await.unwrap();
+
+        assert_eq!(
+            verify(dm.divide(), &key).await.unwrap(),
+            Some("a@b.c".to_string())
+        );
+        assert_eq!(
+            verify(dm.divide(), &format!("{}.00", record.id))
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(verify(dm.divide(), "garbage").await.unwrap(), None);
+
+        let key_v = list_by_owner(dm.divide(), "a@b.c").await.unwrap();
+        assert_eq!(key_v.len(), 1);
+        remove(dm.divide(), &journal, &key_v[0].0).await.unwrap();
+        assert_eq!(verify(dm.divide(), &key).await.unwrap(), None);
+        assert!(list_by_owner(dm.divide(), "a@b.c")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -105,42 +105,35 @@ mod tests {
         EdgeEngine, Path, ScriptTree,
     };
 
-    #[test]
-    fn test() {
-        tokio::runtime::Builder::new_multi_thread()
-            .worker_threads(4)
-            .enable_all()
-            .build()
-            .unwrap()
-            .block_on(async {
-                let dm = MemDataManager::new();
-                let mut edge_engine = EdgeEngine::new(dm.divide());
-                // config.ip, config.port, config.name
-                let name = "test";
-                let ip = "0.0.0.0";
-                let port = "8080";
-                let path = "/test";
-                let script = [
-                    &format!("$->$server_exists = inner root->web_server {name}<-name"),
-                    "$->$web_server = if $->$server_exists ?",
-                    &format!("$->$web_server->name = = {name} _"),
-                    &format!("$->$web_server->ip = = {ip} _"),
-                    &format!("$->$web_server->port = = {port} _"),
-                    &format!("$->$web_server->path = = {path} _"),
-                    "root->web_server += left $->$web_server $->$server_exists",
-                ]
-                .join("\n");
-                edge_engine
-                    .execute1(&ScriptTree {
-                        script,
-                        name: format!("info"),
-                        next_v: vec![],
-                    })
-                    .await
-                    .unwrap();
-                edge_engine.commit().await.unwrap();
-                let rs = dm.get(&Path::from_str("root->web_server")).await.unwrap();
-                assert!(!rs.is_empty());
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test() {
+        let dm = MemDataManager::new();
+        let mut edge_engine = EdgeEngine::new(dm.divide());
+        // config.ip, config.port, config.name
+        let name = "test";
+        let ip = "0.0.0.0";
+        let port = "8080";
+        let path = "/test";
+        let script = [
+            &format!("$->$server_exists = inner root->web_server {name}<-name"),
+            "$->$web_server = if $->$server_exists ?",
+            &format!("$->$web_server->name = = {name} _"),
+            &format!("$->$web_server->ip = = {ip} _"),
+            &format!("$->$web_server->port = = {port} _"),
+            &format!("$->$web_server->path = = {path} _"),
+            "root->web_server += left $->$web_server $->$server_exists",
+        ]
+        .join("\n");
+        edge_engine
+            .execute1(&ScriptTree {
+                script,
+                name: format!("info"),
+                next_v: vec![],
             })
+            .await
+            .unwrap();
+        edge_engine.commit().await.unwrap();
+        let rs = dm.get(&Path::from_str("root->web_server")).await.unwrap();
+        assert!(!rs.is_empty());
     }
 }
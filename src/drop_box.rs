@@ -0,0 +1,227 @@
+//! File request links, or drop boxes, in the edge graph.
+//!
+//! Every drop box is a `root->drop_box` node, addressed by a short random slug. Anyone holding
+//! the slug may upload files into the space of the owner, within the limits of the box, but can
+//! not list or download anything. Boxes are indexed by slug and by owner.
+use std::{io, sync::Arc};
+
+use edge_lib::{data::AsDataManager, Path};
+
+use crate::{
+    graph::{self, execute, get_field, get_u64, Journal},
+    util,
+};
+
+const SLUG_LENGTH: usize = 10;
+
+async fn load_all(
+    dm: Arc<dyn AsDataManager>,
+    node_v: Vec<String>,
+) -> io::Result<Vec<(String, DropBoxRecord)>> {
+    let mut box_v = Vec::with_capacity(node_v.len());
+    for node in node_v {
+        let record = DropBoxRecord::load(dm.as_ref(), &node).await?;
+        box_v.push((node, record));
+    }
+    Ok(box_v)
+}
+
+// Public
+#[derive(Debug, Clone)]
+pub struct DropBoxRecord {
+    pub slug: String,
+    pub owner: String,
+    /// Shown to uploaders.
+    pub title: String,
+    /// Maximum length of each file, 0 if unlimited.
+    pub max_size: u64,
+    /// Maximum number of files, 0 if unlimited.
+    pub max_files: u64,
+    /// Number of files completed through this box.
+    pub file_count: u64,
+    /// 0 if the box never expires.
+    pub expires_at: u64,
+    pub created_at: u64,
+}
+
+impl DropBoxRecord {
+    /// Record a box created now with a new slug.
+    pub fn new(owner: &str, title: &str, max_size: u64, max_files: u64, expires_at: u64) -> Self {
+        Self {
+            slug: util::slug(SLUG_LENGTH),
+            owner: owner.to_string(),
+            title: title.to_string(),
+            max_size,
+            max_files,
+            file_count: 0,
+            expires_at,
+            created_at: util::timestamp(),
+        }
+    }
+
+    /// Load the record of box node `node`.
+    pub async fn load(dm: &dyn AsDataManager, node: &str) -> io::Result<Self> {
+        Ok(Self {
+            slug: get_field(dm, node, "slug").await?,
+            owner: util::unescape(&get_field(dm, node, "owner").await?),
+            title: util::unescape(&get_field(dm, node, "title").await?),
+            max_size: get_u64(dm, node, "max_size").await?,
+            max_files: get_u64(dm, node, "max_files").await?,
+            file_count: get_u64(dm, node, "file_count").await?,
+            expires_at: get_u64(dm, node, "expires_at").await?,
+            created_at: get_u64(dm, node, "created_at").await?,
+        })
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at != 0 && now >= self.expires_at
+    }
+
+    /// Whether another file may be started through this box while `uploading` files are still
+    /// uploading, each holding room for itself.
+    pub fn is_full(&self, uploading: u64) -> bool {
+        self.max_files != 0 && self.file_count.saturating_add(uploading) >= self.max_files
+    }
+
+    /// Whether a file of `length` bytes fits in this box.
+    pub fn fits(&self, length: u64) -> bool {
+        self.max_size == 0 || length <= self.max_size
+    }
+
+    /// Write this record into the graph as a new `root->drop_box` node.
+    pub async fn insert(&self, dm: Arc<dyn AsDataManager>, journal: &Journal) -> io::Result<()> {
+        let script = self.line_v(&graph::new_node()).join("\n");
+        execute(dm, journal, script, "insert drop_box").await
+    }
+
+    /// Script lines writing this record as `root->drop_box` node `node`, with its indexes.
+    fn line_v(&self, node: &str) -> Vec<String> {
+        let mut line_v = vec![
+            format!("$->$drop_box = = {node} _"),
+            format!("$->$drop_box->slug = = {} _", self.slug),
+            format!("$->$drop_box->owner = = {} _", util::escape(&self.owner)),
+            format!("$->$drop_box->title = = {} _", util::escape(&self.title)),
+            format!("$->$drop_box->max_size = = {} _", self.max_size),
+            format!("$->$drop_box->max_files = = {} _", self.max_files),
+            format!("$->$drop_box->file_count = = {} _", self.file_count),
+            format!("$->$drop_box->expires_at = = {} _", self.expires_at),
+            format!("$->$drop_box->created_at = = {} _", self.created_at),
+            format!("root->drop_box += = $->$drop_box _"),
+        ];
+        line_v.extend(graph::index_line_v(
+            "drop_box_by_slug",
+            &self.slug,
+            "drop_box",
+        ));
+        line_v.extend(graph::index_line_v(
+            "drop_box_by_owner",
+            &self.owner,
+            "drop_box",
+        ));
+        line_v
+    }
+}
+
+/// List all box records with their nodes.
+pub async fn list(dm: Arc<dyn AsDataManager>) -> io::Result<Vec<(String, DropBoxRecord)>> {
+    let node_v = dm.get(&Path::from_str("root->drop_box")).await?;
+    load_all(dm, node_v).await
+}
+
+/// List the box records of `owner` with their nodes.
+pub async fn list_by_owner(
+    dm: Arc<dyn AsDataManager>,
+    owner: &str,
+) -> io::Result<Vec<(String, DropBoxRecord)>> {
+    let node_v = graph::lookup(dm.as_ref(), "drop_box_by_owner", owner).await?;
+    load_all(dm, node_v).await
+}
+
+/// Find the box with `slug`, with its node.
+pub async fn find(
+    dm: Arc<dyn AsDataManager>,
+    slug: &str,
+) -> io::Result<Option<(String, DropBoxRecord)>> {
+    let node_v = graph::lookup(dm.as_ref(), "drop_box_by_slug", slug).await?;
+    Ok(load_all(dm, node_v).await?.into_iter().next())
+}
+
+/// Set the file count of box node `node`.
+pub async fn set_file_count(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    node: &str,
+    file_count: u64,
+) -> io::Result<()> {
+    let script = [
+        format!("$->$drop_box = = {node} _"),
+        format!("$->$drop_box->file_count = = {file_count} _"),
+    ]
+    .join("\n");
+    execute(dm, journal, script, "count drop_box").await
+}
+
+/// Remove box node `node` from `root->drop_box` and its indexes.
+pub async fn remove(dm: Arc<dyn AsDataManager>, journal: &Journal, node: &str) -> io::Result<()> {
+    let record = DropBoxRecord::load(dm.as_ref(), node).await?;
+    let index_v = [
+        ("drop_box_by_slug", record.slug.as_str()),
+        ("drop_box_by_owner", record.owner.as_str()),
+    ];
+    graph::remove_node(dm, journal, "drop_box", node, &index_v).await
+}
+
+/// Scripts recreating every box record on its node.
+pub async fn snapshot(dm: Arc<dyn AsDataManager>) -> io::Result<Vec<String>> {
+    Ok(list(dm)
+        .await?
+        .into_iter()
+        .map(|(node, record)| record.line_v(&node).join("\n"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use edge_lib::data::{AsDataManager, MemDataManager};
+
+    use crate::graph::Journal;
+
+    use super::{find, remove, set_file_count, DropBoxRecord};
+
+    #[test]
+    fn test_limits() {
+        let mut record = DropBoxRecord::new("a@b.c", "reports", 10, 1, 100);
+        assert!(record.fits(10));
+        assert!(!record.fits(11));
+        assert!(!record.is_expired(99));
+        assert!(record.is_expired(100));
+        assert!(!record.is_full(0));
+        assert!(record.is_full(1));
+        record.file_count = 1;
+        assert!(record.is_full(0));
+
+        let record = DropBoxRecord::new("a@b.c", "", 0, 0, 0);
+        assert!(record.fits(u64::MAX));
+        assert!(!record.is_expired(u64::MAX));
+        assert!(!record.is_full(u64::MAX));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test() {
+        let dm = MemDataManager::new();
+        let journal = Journal::new();
+        let record = DropBoxRecord::new("a@b.c", "q3 reports", 10, 2, 0);
+        record.insert(dm.divide(), &journal).await.unwrap();
+
+        let (node, found) = find(dm.divide(), &record.slug).await.unwrap().unwrap();
+        assert_eq!(found.title, "q3 reports");
+        set_file_count(dm.divide(), &journal, &node, 2)
+            .await
+            .unwrap();
+        let (node, found) = find(dm.divide(), &record.slug).await.unwrap().unwrap();
+        assert!(found.is_full(0));
+
+        remove(dm.divide(), &journal, &node).await.unwrap();
+        assert!(find(dm.divide(), &record.slug).await.unwrap().is_none());
+    }
+}
@@ -1,18 +1,186 @@
-use std::fmt::Display;
+use std::{fmt::Display, io};
+
+use axum::{
+    body::Body,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
 
 #[derive(Debug)]
 pub enum Error {
     Other(String),
     NotLogin(String),
+    /// Authenticated, but lacking a scope.
+    Forbidden(String),
+    NotFound(String),
+    /// The request is malformed or its values are out of range.
+    BadRequest(String),
+    /// The request contradicts the current state, such as an existing user.
+    Conflict(String),
+    /// The limit in bytes.
+    PayloadTooLarge(u64),
+    /// The blob store is out of space.
+    InsufficientStorage(String),
+    HashMismatch(String),
+    /// The total length of the representation.
+    RangeNotSatisfiable(u64),
+    /// The `exp` of the token.
+    TokenExpired(u64),
+    /// Seconds until another attempt is allowed.
+    TooManyRequests(u64),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+impl Error {
+    /// A stable code for clients to match on, unlike the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Other(_) => "internal",
+            Error::NotLogin(_) => "not_login",
+            Error::Forbidden(_) => "forbidden",
+            Error::NotFound(_) => "not_found",
+            Error::BadRequest(_) => "bad_request",
+            Error::Conflict(_) => "conflict",
+            Error::PayloadTooLarge(_) => "payload_too_large",
+            Error::InsufficientStorage(_) => "insufficient_storage",
+            Error::HashMismatch(_) => "hash_mismatch",
+            Error::RangeNotSatisfiable(_) => "range_not_satisfiable",
+            Error::TokenExpired(_) => "token_expired",
+            Error::TooManyRequests(_) => "too_many_requests",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::Other(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::NotLogin(_) => StatusCode::UNAUTHORIZED,
+            Error::Forbidden(_) => StatusCode::FORBIDDEN,
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Error::Conflict(_) => StatusCode::CONFLICT,
+            Error::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::InsufficientStorage(_) => StatusCode::INSUFFICIENT_STORAGE,
+            Error::HashMismatch(_) => StatusCode::BAD_REQUEST,
+            Error::RangeNotSatisfiable(_) => StatusCode::RANGE_NOT_SATISFIABLE,
+            Error::TokenExpired(_) => StatusCode::UNAUTHORIZED,
+            Error::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+
+    /// Map an error of reading blob `key`, the only place where a missing file is not a bug.
+    pub fn from_blob_read(e: io::Error, key: &str) -> Self {
+        match e.kind() {
+            io::ErrorKind::NotFound => Error::NotFound(format!("{key} not found")),
+            _ => Error::from(e),
+        }
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Error::Other(msg) => write!(f, "{msg}"),
             Error::NotLogin(msg) => write!(f, "{msg}"),
+            Error::Forbidden(msg) => write!(f, "{msg}"),
+            Error::NotFound(msg) => write!(f, "{msg}"),
+            Error::BadRequest(msg) => write!(f, "{msg}"),
+            Error::Conflict(msg) => write!(f, "{msg}"),
+            Error::PayloadTooLarge(limit) => write!(f, "payload is larger than {limit}"),
+            Error::InsufficientStorage(msg) => write!(f, "{msg}"),
+            Error::HashMismatch(msg) => write!(f, "{msg}"),
+            Error::RangeNotSatisfiable(length) => write!(f, "range not satisfiable in {length}"),
+            Error::TokenExpired(exp) => write!(f, "token expired at {exp}"),
+            Error::TooManyRequests(retry_after) => {
+                write!(f, "too many attempts, retry in {retry_after} seconds")
+            }
+        }
+    }
+}
+
+/// Errors of the blob store, where a full disk is not a bug. A missing file is, unless it is a
+/// blob being read, see `Error::from_blob_read`.
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded => {
+                Error::InsufficientStorage(e.to_string())
+            }
+            _ => Error::Other(e.to_string()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    code: &'static str,
+    message: String,
+}
+
+/// `{"code": ..., "message": ...}` with the status of the variant. Every failed request is
+/// logged here.
+impl IntoResponse for Error {
+    fn into_response(self) -> Response<Body> {
+        let status = self.status();
+        log::warn!("{self}\nwhen respond {status}");
+        let body = Json(ErrorBody {
+            code: self.code(),
+            message: self.to_string(),
+        });
+        match self {
+            Error::RangeNotSatisfiable(length) => (
+                status,
+                [(header::CONTENT_RANGE, format!("bytes */{length}"))],
+                body,
+            )
+                .into_response(),
+            Error::TooManyRequests(retry_after) => (
+                status,
+                [(header::RETRY_AFTER, retry_after.to_string())],
+                body,
+            )
+                .into_response(),
+            _ => (status, body).into_response(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        let res = Error::NotFound(format!("no file")).into_response();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            res.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+
+        let res = Error::RangeNotSatisfiable(10).into_response();
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(
+            res.headers().get(header::CONTENT_RANGE).unwrap(),
+            "bytes */10"
+        );
+
+        let res = Error::TooManyRequests(60).into_response();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers().get(header::RETRY_AFTER).unwrap(), "60");
+
+        let e = Error::from(io::Error::from(io::ErrorKind::StorageFull));
+        assert_eq!(e.code(), "insufficient_storage");
+        assert_eq!(e.status(), StatusCode::INSUFFICIENT_STORAGE);
+
+        // A missing file is a bug, unless it is the blob asked for.
+        let e = Error::from(io::Error::from(io::ErrorKind::NotFound));
+        assert_eq!(e.code(), "internal");
+        let e = Error::from_blob_read(io::Error::from(io::ErrorKind::NotFound), "00");
+        assert_eq!(e.code(), "not_found");
+        let e = Error::from_blob_read(io::Error::from(io::ErrorKind::StorageFull), "00");
+        assert_eq!(e.code(), "insufficient_storage");
+    }
+}
@@ -0,0 +1,233 @@
+//! File records in the edge graph.
+//!
+//! Every finalized upload is recorded as a `root->file` node, so that endpoints and edge scripts
+//! can query files the same way `HttpConnector` queries `root->web_server`. String fields are
+//! stored escaped, see `util::escape`. Files are indexed by md5 and sha256 under
+//! `root->file_by_key`, and by owner under `root->file_by_owner`.
+use std::{io, sync::Arc};
+
+use edge_lib::{data::AsDataManager, Path};
+
+use crate::{
+    graph::{self, get_field, Journal},
+    util,
+};
+
+async fn load_all(
+    dm: Arc<dyn AsDataManager>,
+    node_v: Vec<String>,
+) -> io::Result<Vec<(String, FileRecord)>> {
+    let mut file_v = Vec::with_capacity(node_v.len());
+    for node in node_v {
+        let record = FileRecord::load(dm.as_ref(), &node).await?;
+        file_v.push((node, record));
+    }
+    Ok(file_v)
+}
+
+// Public
+#[derive(Debug, Clone)]
+pub struct FileRecord {
+    pub md5: String,
+    /// Empty for files stored before sha256 addressing.
+    pub sha256: String,
+    pub length: u64,
+    pub name: String,
+    pub mime: String,
+    pub owner: String,
+    pub created_at: u64,
+}
+
+impl FileRecord {
+    /// Record a file created now, guessing its mime type from the extension of `name`.
+    ///
+    /// An empty `name` falls back to `md5`.
+    pub fn new(md5: &str, sha256: &str, length: u64, name: &str, owner: &str) -> Self {
+        let name = if name.is_empty() { md5 } else { name };
+        Self {
+            md5: md5.to_string(),
+            sha256: sha256.to_string(),
+            length,
+            name: name.to_string(),
+            mime: mime_guess::from_path(name)
+                .first_or_octet_stream()
+                .essence_str()
+                .to_string(),
+            owner: owner.to_string(),
+            created_at: util::timestamp(),
+        }
+    }
+
+    /// Load the record of file node `node`.
+    pub async fn load(dm: &dyn AsDataManager, node: &str) -> io::Result<Self> {
+        Ok(Self {
+            md5: get_field(dm, node, "md5").await?,
+            sha256: get_field(dm, node, "sha256").await?,
+            length: get_field(dm, node, "length").await?.parse().unwrap_or(0),
+            name: util::unescape(&get_field(dm, node, "name").await?),
+            mime: util::unescape(&get_field(dm, node, "mime").await?),
+            owner: util::unescape(&get_field(dm, node, "owner").await?),
+            created_at: get_field(dm, node, "created_at")
+                .await?
+                .parse()
+                .unwrap_or(0),
+        })
+    }
+
+    /// Key of the blob of this file.
+    pub fn key(&self) -> &str {
+        if self.sha256.is_empty() {
+            &self.md5
+        } else {
+            &self.sha256
+        }
+    }
+
+    /// Write this record into the graph as a new `root->file` node.
+    pub async fn insert(&self, dm: Arc<dyn AsDataManager>, journal: &Journal) -> io::Result<()> {
+        let script = self.line_v(&graph::new_node()).join("\n");
+        graph::execute(dm, journal, script, "insert file").await
+    }
+
+    /// Script lines writing this record as `root->file` node `node`, with its indexes.
+    fn line_v(&self, node: &str) -> Vec<String> {
+        let mut line_v = vec![
+            format!("$->$file = = {node} _"),
+            format!("$->$file->md5 = = {} _", self.md5),
+        ];
+        if !self.sha256.is_empty() {
+            line_v.push(format!("$->$file->sha256 = = {} _", self.sha256));
+        }
+        line_v.push(format!("$->$file->length = = {} _", self.length));
+        line_v.push(format!("$->$file->name = = {} _", util::escape(&self.name)));
+        line_v.push(format!("$->$file->mime = = {} _", util::escape(&self.mime)));
+        line_v.push(format!(
+            "$->$file->owner = = {} _",
+            util::escape(&self.owner)
+        ));
+        line_v.push(format!("$->$file->created_at = = {} _", self.created_at));
+        line_v.push(format!("root->file += = $->$file _"));
+        line_v.extend(graph::index_line_v("file_by_key", &self.md5, "file"));
+        line_v.extend(graph::index_line_v("file_by_key", &self.sha256, "file"));
+        line_v.extend(graph::index_line_v("file_by_owner", &self.owner, "file"));
+        line_v
+    }
+}
+
+/// List all file records with their nodes.
+pub async fn list(dm: Arc<dyn AsDataManager>) -> io::Result<Vec<(String, FileRecord)>> {
+    let node_v = dm.get(&Path::from_str("root->file")).await?;
+    load_all(dm, node_v).await
+}
+
+/// List the file records with md5 or sha256 `hash`, with their nodes.
+pub async fn find(dm: Arc<dyn AsDataManager>, hash: &str) -> io::Result<Vec<(String, FileRecord)>> {
+    let mut node_v = graph::lookup(dm.as_ref(), "file_by_key", hash).await?;
+    node_v.dedup();
+    load_all(dm, node_v).await
+}
+
+/// List the file records of `owner`, with their nodes.
+pub async fn list_by_owner(
+    dm: Arc<dyn AsDataManager>,
+    owner: &str,
+) -> io::Result<Vec<(String, FileRecord)>> {
+    let node_v = graph::lookup(dm.as_ref(), "file_by_owner", owner).await?;
+    load_all(dm, node_v).await
+}
+
+/// Rename file node `node` to `name`, guessing its mime type again.
+pub async fn rename(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    node: &str,
+    name: &str,
+) -> io::Result<()> {
+    let mime = mime_guess::from_path(name).first_or_octet_stream();
+    let script = [
+        format!("$->$file = = {node} _"),
+        format!("$->$file->name = = {} _", util::escape(name)),
+        format!("$->$file->mime = = {} _", util::escape(mime.essence_str())),
+    ]
+    .join("\n");
+    graph::execute(dm, journal, script, "rename file").await
+}
+
+/// Remove file node `node` from `root->file` and its indexes.
+pub async fn remove(dm: Arc<dyn AsDataManager>, journal: &Journal, node: &str) -> io::Result<()> {
+    let record = FileRecord::load(dm.as_ref(), node).await?;
+    let index_v = [
+        ("file_by_key", record.md5.as_str()),
+        ("file_by_key", record.sha256.as_str()),
+        ("file_by_owner", record.owner.as_str()),
+    ];
+    graph::remove_node(dm, journal, "file", node, &index_v).await
+}
+
+/// Scripts recreating every file record on its node.
+pub async fn snapshot(dm: Arc<dyn AsDataManager>) -> io::Result<Vec<String>> {
+    Ok(list(dm)
+        .await?
+        .into_iter()
+        .map(|(node, record)| record.line_v(&node).join("\n"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use edge_lib::{
+        data::{AsDataManager, MemDataManager},
+        Path,
+    };
+
+    use crate::graph::Journal;
+
+    use sha2::{Digest, Sha256};
+
+    use super::{find, list, list_by_owner, remove, rename, FileRecord};
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test() {
+        let dm = MemDataManager::new();
+        let journal = Journal::new();
+        let md5 = format!("{:x}", md5::compute(b"hello"));
+        let record = FileRecord::new(
+            &md5,
+            &format!("{:x}", Sha256::digest(b"hello")),
+            5,
+            "hello world.txt",
+            "a@b.c",
+        );
+        assert_eq!(record.mime, "text/plain");
+        record.insert(dm.divide(), &journal).await.unwrap();
+
+        let rs = dm.get(&Path::from_str("root->file->md5")).await.unwrap();
+        assert_eq!(rs, vec![md5.clone()]);
+        let rs = dm.get(&Path::from_str("root->file->name")).await.unwrap();
+        assert_eq!(rs, vec!["hello%20world.txt".to_string()]);
+
+        let file_v = list(dm.divide()).await.unwrap();
+        assert_eq!(file_v.len(), 1);
+        assert_eq!(file_v[0].1.name, "hello world.txt");
+        assert_eq!(file_v[0].1.owner, "a@b.c");
+        assert_eq!(file_v[0].1.key(), record.sha256);
+        assert_eq!(find(dm.divide(), &md5).await.unwrap().len(), 1);
+        assert_eq!(find(dm.divide(), &record.sha256).await.unwrap().len(), 1);
+        assert_eq!(list_by_owner(dm.divide(), "a@b.c").await.unwrap().len(), 1);
+
+        rename(dm.divide(), &journal, &file_v[0].0, "a.png")
+            .await
+            .unwrap();
+        let file_v = list(dm.divide()).await.unwrap();
+        assert_eq!(file_v[0].1.name, "a.png");
+        assert_eq!(file_v[0].1.mime, "image/png");
+
+        remove(dm.divide(), &journal, &file_v[0].0).await.unwrap();
+        assert!(list(dm.divide()).await.unwrap().is_empty());
+        assert!(find(dm.divide(), &md5).await.unwrap().is_empty());
+        assert!(list_by_owner(dm.divide(), "a@b.c")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
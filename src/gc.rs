@@ -0,0 +1,123 @@
+//! Garbage collection of abandoned uploads.
+//!
+//! A temp blob is reclaimed once it has no live upload session and has not been written for
+//! longer than the ttl. Expired session files are removed as well.
+use std::{io, sync::Arc, time::Duration};
+
+use tokio::time;
+
+use crate::{session::SessionStore, storage::AsBlobStore, util};
+
+// Public
+#[derive(Debug, Default)]
+pub struct Reclaimed {
+    pub session_id_v: Vec<String>,
+    pub temp_id_v: Vec<String>,
+    /// Total length of the reclaimed temp blobs.
+    pub length: u64,
+}
+
+pub struct TempCollector {
+    store: Arc<dyn AsBlobStore>,
+    sessions: Arc<SessionStore>,
+    ttl: u64,
+    interval: Duration,
+}
+
+impl TempCollector {
+    /// Reclaim temp blobs idle for longer than `ttl` seconds, every `interval` seconds.
+    pub fn new(
+        store: Arc<dyn AsBlobStore>,
+        sessions: Arc<SessionStore>,
+        ttl: u64,
+        interval: u64,
+    ) -> Self {
+        Self {
+            store,
+            sessions,
+            ttl,
+            interval: Duration::from_secs(interval),
+        }
+    }
+
+    pub async fn run(self) -> io::Result<()> {
+        loop {
+            if let Err(e) = self.collect().await {
+                log::warn!("{e}\nwhen collect");
+            }
+
+            time::sleep(self.interval).await;
+        }
+    }
+
+    pub async fn collect(&self) -> io::Result<Reclaimed> {
+        self.collect_at(util::timestamp()).await
+    }
+
+    async fn collect_at(&self, now: u64) -> io::Result<Reclaimed> {
+        let mut reclaimed = Reclaimed::default();
+        for session in self.sessions.list_expired(now).await? {
+            self.sessions.remove(&session.id).await?;
+            log::info!("reclaimed session {}", session.id);
+            reclaimed.session_id_v.push(session.id);
+        }
+        for temp in self.store.list_temp().await? {
+            if now.saturating_sub(temp.modified_at) <= self.ttl {
+                continue;
+            }
+            if self.sessions.get(&temp.id).await?.is_some() {
+                continue;
+            }
+            self.store.abort(&temp.id).await?;
+            log::info!("reclaimed temp {}, {} bytes", temp.id, temp.length);
+            reclaimed.length += temp.length;
+            reclaimed.temp_id_v.push(temp.id);
+        }
+        log::info!(
+            "reclaimed {} sessions and {} temps, {} bytes",
+            reclaimed.session_id_v.len(),
+            reclaimed.temp_id_v.len(),
+            reclaimed.length
+        );
+        Ok(reclaimed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{
+        session::SessionStore,
+        storage::{AsBlobStore, MemBlobStore},
+        util,
+    };
+
+    use super::TempCollector;
+
+    #[tokio::test]
+    async fn test() {
+        let dir = std::env::temp_dir().join(format!("pool_test_gc_{}", rand::random::<u64>()));
+        let store = Arc::new(MemBlobStore::new());
+        let sessions = Arc::new(SessionStore::new(&dir, 60));
+        let session = sessions.create("md5", 4, "a.txt", "email").await.unwrap();
+        store.begin(&session.id, 4).await.unwrap();
+        store.write_at("orphan", 0, b"hel".to_vec()).await.unwrap();
+
+        let collector = TempCollector::new(store.clone(), sessions.clone(), 60, 60);
+        let now = util::timestamp();
+
+        // Nothing is idle yet.
+        let reclaimed = collector.collect_at(now).await.unwrap();
+        assert!(reclaimed.temp_id_v.is_empty());
+        assert!(reclaimed.session_id_v.is_empty());
+
+        let reclaimed = collector.collect_at(now + 61).await.unwrap();
+        assert_eq!(reclaimed.session_id_v, vec![session.id.clone()]);
+        assert_eq!(reclaimed.temp_id_v.len(), 2);
+        assert_eq!(reclaimed.length, 7);
+        assert!(store.list_temp().await.unwrap().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
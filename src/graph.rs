@@ -0,0 +1,350 @@
+//! Helpers shared by the records in the edge graph.
+//!
+//! Every kind of record is a list of nodes under `root`, such as `root->file`, whose fields are
+//! edges of the node. String fields are stored escaped, see `util::escape`.
+//!
+//! Records looked up by a field are also indexed under `root->{index}`, such as
+//! `root->file_by_key->{code}`, where the code is derived from the value of the field, so that a
+//! lookup does not scan the whole list.
+//!
+//! A journal records every script executed here on one graph, and is replayed into the graph on
+//! the next start, so that records survive a restart like blobs do. Record nodes get their ids
+//! from `new_node` rather than `?`, so that replaying recreates the same nodes. Once enough
+//! scripts pile up, the journal is compacted into a snapshot of the records it holds.
+use std::{
+    io,
+    path::{Path as FsPath, PathBuf},
+    sync::Arc,
+};
+
+use edge_lib::{data::AsDataManager, EdgeEngine, Path, ScriptTree};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::Mutex,
+};
+
+use crate::{
+    acl, api_key, drop_box, file, refresh_token, revocation, role, share_link, user, util,
+};
+
+/// Scripts appended since the last snapshot that trigger compaction, unless the snapshot has
+/// more.
+const COMPACT_LINE_COUNT: usize = 1024;
+
+struct JournalFile {
+    path: PathBuf,
+    file: File,
+    /// Lines in the file.
+    line_count: usize,
+    /// Lines of the snapshot the file starts with.
+    snapshot_line_count: usize,
+}
+
+impl JournalFile {
+    async fn append(&mut self, script: &str) -> io::Result<()> {
+        let mut line = serde_json::to_string(script).map_err(io::Error::other)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes()).await?;
+        self.file.flush().await?;
+        self.line_count += 1;
+        Ok(())
+    }
+
+    fn is_due(&self) -> bool {
+        self.line_count - self.snapshot_line_count
+            >= std::cmp::max(COMPACT_LINE_COUNT, self.snapshot_line_count)
+    }
+
+    /// Replace the file with a snapshot of the records in `dm`.
+    ///
+    /// The snapshot is written aside and renamed over the file, so that a crash leaves either
+    /// the old journal or the new one.
+    async fn compact(&mut self, dm: Arc<dyn AsDataManager>) -> io::Result<()> {
+        let script_v = snapshot(dm).await?;
+        let mut temp_path = self.path.clone().into_os_string();
+        temp_path.push(".temp");
+        let temp_path = PathBuf::from(temp_path);
+        let mut content = String::new();
+        for script in &script_v {
+            content.push_str(&serde_json::to_string(script).map_err(io::Error::other)?);
+            content.push('\n');
+        }
+        let mut temp = File::create(&temp_path).await?;
+        temp.write_all(content.as_bytes()).await?;
+        temp.sync_all().await?;
+        fs::rename(&temp_path, &self.path).await?;
+        self.file = OpenOptions::new().append(true).open(&self.path).await?;
+        log::info!(
+            "compacted {} lines into {} in {}",
+            self.line_count,
+            script_v.len(),
+            self.path.display()
+        );
+        self.line_count = script_v.len();
+        self.snapshot_line_count = script_v.len();
+        Ok(())
+    }
+}
+
+/// Scripts recreating every record in `dm` on its node, a script per record.
+async fn snapshot(dm: Arc<dyn AsDataManager>) -> io::Result<Vec<String>> {
+    let mut script_v = user::snapshot(dm.clone()).await?;
+    script_v.extend(role::snapshot(dm.clone()).await?);
+    script_v.extend(file::snapshot(dm.clone()).await?);
+    script_v.extend(acl::snapshot(dm.clone()).await?);
+    script_v.extend(api_key::snapshot(dm.clone()).await?);
+    script_v.extend(refresh_token::snapshot(dm.clone()).await?);
+    script_v.extend(revocation::snapshot(dm.clone()).await?);
+    script_v.extend(share_link::snapshot(dm.clone()).await?);
+    script_v.extend(drop_box::snapshot(dm).await?);
+    Ok(script_v)
+}
+
+/// Code of `value` in an index, as values may hold characters that codes can not.
+fn index_code(value: &str) -> String {
+    let hex: String = value.bytes().map(|b| format!("{b:02x}")).collect();
+    format!("k{hex}")
+}
+
+async fn run(dm: Arc<dyn AsDataManager>, script: String, when: &str) -> io::Result<()> {
+    let mut edge_engine = EdgeEngine::new(dm);
+    edge_engine
+        .execute1(&ScriptTree {
+            script,
+            name: format!("graph"),
+            next_v: vec![],
+        })
+        .await
+        .map_err(|e| io::Error::other(format!("{e}\nwhen {when}")))?;
+    edge_engine.commit().await
+}
+
+// Public
+/// Journal of the scripts executed on one graph.
+#[derive(Default)]
+pub struct Journal {
+    /// Locked across executing and appending a script, so that scripts are journaled in the
+    /// order they were executed. `None` if nothing is journaled.
+    file: Mutex<Option<JournalFile>>,
+}
+
+impl Journal {
+    /// A journal that records nothing, for graphs that need not survive a restart.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replay the journal at `path` into `dm` and compact it, then journal every script executed
+    /// through the returned journal.
+    ///
+    /// A journal is a line of JSON per script. A torn last line, left by a crash while appending,
+    /// is cut off.
+    pub async fn open(dm: Arc<dyn AsDataManager>, path: &FsPath) -> io::Result<Self> {
+        let content = util::not_found_as_none(fs::read_to_string(path).await)?.unwrap_or_default();
+        let mut valid_len = 0;
+        let mut line_count = 0;
+        for line in content.split_inclusive('\n') {
+            let script: String = match line
+                .strip_suffix('\n')
+                .map(serde_json::from_str)
+                .and_then(Result::ok)
+            {
+                Some(r) => r,
+                None => {
+                    log::warn!("torn line at {valid_len}\nwhen replay {}", path.display());
+                    break;
+                }
+            };
+            run(dm.clone(), script, "replay journal").await?;
+            valid_len += line.len();
+            line_count += 1;
+        }
+        log::info!("replayed {valid_len} bytes from {}", path.display());
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        file.set_len(valid_len as u64).await?;
+        let mut file = JournalFile {
+            path: path.to_path_buf(),
+            file,
+            line_count,
+            snapshot_line_count: 0,
+        };
+        file.compact(dm).await?;
+        Ok(Self {
+            file: Mutex::new(Some(file)),
+        })
+    }
+
+    /// Rewrite the journal as a snapshot of the records in `dm`.
+    pub async fn compact(&self, dm: Arc<dyn AsDataManager>) -> io::Result<()> {
+        match self.file.lock().await.as_mut() {
+            Some(file) => file.compact(dm).await,
+            None => Ok(()),
+        }
+    }
+}
+
+/// Id of a new record node.
+pub fn new_node() -> String {
+    util::byte_v2hex(&rand::random::<[u8; 16]>())
+}
+
+/// Execute `script` and commit it, reporting errors as happening `when`, then journal it.
+pub async fn execute(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    script: String,
+    when: &str,
+) -> io::Result<()> {
+    let mut file = journal.file.lock().await;
+    run(dm.clone(), script.clone(), when).await?;
+    if let Some(file) = file.as_mut() {
+        file.append(&script).await?;
+        if file.is_due() {
+            // The script is journaled already, a later compaction may succeed.
+            if let Err(e) = file.compact(dm).await {
+                log::warn!("{e}\nwhen compact {}", file.path.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Get field `field` of node `node`, empty if it is not set.
+pub async fn get_field(dm: &dyn AsDataManager, node: &str, field: &str) -> io::Result<String> {
+    let rs = dm.get(&Path::from_str(&format!("{node}->{field}"))).await?;
+    Ok(rs.into_iter().next().unwrap_or_default())
+}
+
+/// Get numeric field `field` of node `node`, 0 if it is not set.
+pub async fn get_u64(dm: &dyn AsDataManager, node: &str, field: &str) -> io::Result<u64> {
+    Ok(get_field(dm, node, field).await?.parse().unwrap_or(0))
+}
+
+/// Script lines adding node `$->${var}` to index `index` under `value`, none if `value` is empty.
+pub fn index_line_v(index: &str, value: &str, var: &str) -> Vec<String> {
+    if value.is_empty() {
+        return vec![];
+    }
+    let code = index_code(value);
+    vec![
+        format!("$->${index} = if root->{index} ?"),
+        format!("root->{index} = = $->${index} _"),
+        format!("$->${index}->{code} += = $->${var} _"),
+    ]
+}
+
+/// Get the nodes under `value` in index `index`.
+pub async fn lookup(dm: &dyn AsDataManager, index: &str, value: &str) -> io::Result<Vec<String>> {
+    if value.is_empty() {
+        return Ok(vec![]);
+    }
+    let code = index_code(value);
+    dm.get(&Path::from_str(&format!("root->{index}->{code}")))
+        .await
+}
+
+/// Remove node `node` from `root->{list}` and from each index in `index_v` under its value.
+pub async fn remove_node(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    list: &str,
+    node: &str,
+    index_v: &[(&str, &str)],
+) -> io::Result<()> {
+    let mut line_v = vec![
+        format!("$->$node = = {node} _"),
+        format!("root->{list} = left root->{list} $->$node"),
+    ];
+    for (index, value) in index_v.iter().filter(|(_, value)| !value.is_empty()) {
+        let code = index_code(value);
+        line_v.push(format!(
+            "root->{index}->{code} = left root->{index}->{code} $->$node"
+        ));
+    }
+    execute(dm, journal, line_v.join("\n"), &format!("remove {list}")).await
+}
+
+#[cfg(test)]
+mod tests {
+    use edge_lib::data::{AsDataManager, MemDataManager};
+
+    use crate::{
+        file::{self, FileRecord},
+        role::{self, Role},
+        util,
+    };
+
+    use super::{Journal, COMPACT_LINE_COUNT};
+
+    fn line_count(path: &std::path::Path) -> usize {
+        std::fs::read_to_string(path).unwrap().lines().count()
+    }
+
+    #[tokio::test]
+    async fn test_journal() {
+        let dir = std::env::temp_dir().join(format!("pool_test_journal_{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("graph.journal");
+        let md5 = util::byte_v2hex(&rand::random::<[u8; 16]>());
+        let email = format!("{md5}@b.c");
+
+        let dm = MemDataManager::new();
+        let journal = Journal::open(dm.divide(), &path).await.unwrap();
+        FileRecord::new(&md5, "", 5, "a.txt", &email)
+            .insert(dm.divide(), &journal)
+            .await
+            .unwrap();
+        FileRecord::new(&md5, "", 5, "b.txt", &email)
+            .insert(dm.divide(), &journal)
+            .await
+            .unwrap();
+        let (node, _) = file::find(dm.divide(), &md5)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|(_, record)| record.name == "b.txt")
+            .unwrap();
+        file::remove(dm.divide(), &journal, &node).await.unwrap();
+        role::assign(dm.divide(), &journal, &email, Role::Reader)
+            .await
+            .unwrap();
+        drop(journal);
+        assert_eq!(line_count(&path), 4);
+        // A crash while appending leaves a torn line.
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        std::io::Write::write_all(&mut file, b"\"root->x = =").unwrap();
+
+        // A restarted pool replays the journal into an empty graph, then keeps a snapshot.
+        let dm = MemDataManager::new();
+        let journal = Journal::open(dm.divide(), &path).await.unwrap();
+        assert_eq!(line_count(&path), 2);
+        let file_v = file::find(dm.divide(), &md5).await.unwrap();
+        assert_eq!(file_v.len(), 1);
+        assert_eq!(file_v[0].1.name, "a.txt");
+        assert_eq!(role::find(dm.divide(), &email).await.unwrap(), Role::Reader);
+
+        // Scripts piling up are compacted away.
+        for _ in 0..COMPACT_LINE_COUNT {
+            role::assign(dm.divide(), &journal, &email, Role::Writer)
+                .await
+                .unwrap();
+        }
+        assert!(line_count(&path) <= COMPACT_LINE_COUNT);
+        drop(journal);
+        let dm = MemDataManager::new();
+        Journal::open(dm.divide(), &path).await.unwrap();
+        assert_eq!(file::find(dm.divide(), &md5).await.unwrap().len(), 1);
+        assert_eq!(role::find(dm.divide(), &email).await.unwrap(), Role::Writer);
+        assert_eq!(role::list(dm.divide()).await.unwrap().len(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,259 @@
+//! Ed25519 public keys in JWKS form.
+//!
+//! A pool that does not sign tokens itself trusts the public keys of a central issuer, read from
+//! a JWKS file or URL and cached for a while. One task refreshes stale keys while the others keep
+//! using them, and a failed refresh is not retried for a while.
+use std::{io, time::Duration};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use ed25519_dalek::VerifyingKey;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::util;
+
+const CACHE_TTL: u64 = 300;
+const RETRY_AFTER: u64 = 30;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize)]
+struct Jwk {
+    kty: String,
+    #[serde(default)]
+    crv: String,
+    #[serde(default)]
+    kid: String,
+    #[serde(default)]
+    x: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    alg: Option<String>,
+    #[serde(default, rename = "use", skip_serializing_if = "Option::is_none")]
+    usage: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Default)]
+struct Cache {
+    key_v: Option<Vec<PublicKey>>,
+    fetched_at: u64,
+    /// When to fetch again after a failure, 0 after a success.
+    retry_at: u64,
+    error: String,
+}
+
+impl Cache {
+    /// Get the keys or the last error, unless it is time to fetch them.
+    fn get(&self, now: u64) -> Option<io::Result<Vec<PublicKey>>> {
+        match &self.key_v {
+            Some(key_v)
+                if now.saturating_sub(self.fetched_at) < CACHE_TTL || now < self.retry_at =>
+            {
+                Some(Ok(key_v.clone()))
+            }
+            None if now < self.retry_at => Some(Err(io::Error::other(self.error.clone()))),
+            _ => None,
+        }
+    }
+}
+
+async fn fetch(client: &reqwest::Client, location: &str) -> io::Result<Vec<u8>> {
+    if location.starts_with("http://") || location.starts_with("https://") {
+        let res = client
+            .get(location)
+            .send()
+            .await
+            .map_err(io::Error::other)?;
+        if !res.status().is_success() {
+            return Err(io::Error::other(format!(
+                "{} when get {location}",
+                res.status()
+            )));
+        }
+        Ok(res.bytes().await.map_err(io::Error::other)?.to_vec())
+    } else {
+        tokio::fs::read(location).await
+    }
+}
+
+// Public
+#[derive(Debug, Clone)]
+pub struct PublicKey {
+    pub kid: String,
+    pub key: VerifyingKey,
+    /// Whether the key is of another issuer, read from `token_jwks`.
+    pub is_foreign: bool,
+}
+
+impl PublicKey {
+    pub fn to_jwk(&self) -> serde_json::Value {
+        serde_json::to_value(Jwk {
+            kty: "OKP".to_string(),
+            crv: "Ed25519".to_string(),
+            kid: self.kid.clone(),
+            x: URL_SAFE_NO_PAD.encode(self.key.as_bytes()),
+            alg: Some("EdDSA".to_string()),
+            usage: Some("sig".to_string()),
+        })
+        .unwrap()
+    }
+}
+
+/// Parse the Ed25519 keys of a JWKS document, skipping keys of other types.
+pub fn parse(content: &[u8]) -> io::Result<Vec<PublicKey>> {
+    let jwks: Jwks = serde_json::from_slice(content).map_err(io::Error::other)?;
+    let mut key_v = Vec::with_capacity(jwks.keys.len());
+    for jwk in jwks.keys {
+        if jwk.kty != "OKP" || jwk.crv != "Ed25519" {
+            continue;
+        }
+        let x = URL_SAFE_NO_PAD
+            .decode(jwk.x.as_bytes())
+            .map_err(io::Error::other)?;
+        let x: [u8; 32] = x
+            .try_into()
+            .map_err(|_| io::Error::other(format!("invalid x of {}", jwk.kid)))?;
+        let key = VerifyingKey::from_bytes(&x).map_err(io::Error::other)?;
+        key_v.push(PublicKey {
+            kid: jwk.kid,
+            key,
+            is_foreign: true,
+        });
+    }
+    Ok(key_v)
+}
+
+pub fn to_jwks(key_v: &[PublicKey]) -> serde_json::Value {
+    serde_json::json!({ "keys": key_v.iter().map(|key| key.to_jwk()).collect::<Vec<_>>() })
+}
+
+/// Public keys read from a JWKS file or URL.
+pub struct JwksSource {
+    location: String,
+    client: reqwest::Client,
+    cache: Mutex<Cache>,
+    /// Held by the task fetching the keys.
+    refresh: Mutex<()>,
+}
+
+impl JwksSource {
+    /// An empty `location` trusts no key.
+    pub fn new(location: &str) -> Self {
+        Self {
+            location: location.to_string(),
+            client: reqwest::Client::builder()
+                .connect_timeout(CONNECT_TIMEOUT)
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .unwrap(),
+            cache: Mutex::new(Cache::default()),
+            refresh: Mutex::new(()),
+        }
+    }
+
+    /// Get the keys, reading them again once the cache is stale.
+    ///
+    /// Stale keys are still used while they are being read again, or if they can not be read.
+    pub async fn keys(&self) -> io::Result<Vec<PublicKey>> {
+        if self.location.is_empty() {
+            return Ok(Vec::new());
+        }
+        if let Some(r) = self.cache.lock().await.get(util::timestamp()) {
+            return r;
+        }
+        let _guard = match self.refresh.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => {
+                if let Some(key_v) = self.cache.lock().await.key_v.clone() {
+                    return Ok(key_v);
+                }
+                self.refresh.lock().await
+            }
+        };
+        // Another task may have fetched the keys meanwhile.
+        if let Some(r) = self.cache.lock().await.get(util::timestamp()) {
+            return r;
+        }
+
+        let r = fetch(&self.client, &self.location)
+            .await
+            .and_then(|content| parse(&content));
+        let mut cache = self.cache.lock().await;
+        let now = util::timestamp();
+        match r {
+            Ok(key_v) => {
+                *cache = Cache {
+                    key_v: Some(key_v.clone()),
+                    fetched_at: now,
+                    ..Default::default()
+                };
+                Ok(key_v)
+            }
+            Err(e) => {
+                log::warn!("{e}\nwhen fetch {}", self.location);
+                cache.retry_at = now + RETRY_AFTER;
+                cache.error = e.to_string();
+                match &cache.key_v {
+                    Some(key_v) => Ok(key_v.clone()),
+                    None => Err(e),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::SigningKey;
+
+    use super::{parse, to_jwks, JwksSource, PublicKey};
+
+    #[test]
+    fn test() {
+        let key = PublicKey {
+            kid: "0".to_string(),
+            key: SigningKey::from_bytes(&[7; 32]).verifying_key(),
+            is_foreign: false,
+        };
+        let mut jwks = to_jwks(&[key.clone()]);
+        jwks["keys"]
+            .as_array_mut()
+            .unwrap()
+            .push(serde_json::json!({ "kty": "RSA", "kid": "1", "n": "", "e": "AQAB" }));
+
+        let key_v = parse(jwks.to_string().as_bytes()).unwrap();
+        assert_eq!(key_v.len(), 1);
+        assert_eq!(key_v[0].kid, "0");
+        assert_eq!(key_v[0].key, key.key);
+        assert!(key_v[0].is_foreign);
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let path = std::env::temp_dir().join(format!("pool_test_jwks_{}", rand::random::<u64>()));
+        let source = JwksSource::new(path.to_str().unwrap());
+        assert!(source.keys().await.is_err());
+
+        // A failed fetch is not retried at once.
+        let key = PublicKey {
+            kid: "0".to_string(),
+            key: SigningKey::from_bytes(&[7; 32]).verifying_key(),
+            is_foreign: false,
+        };
+        std::fs::write(&path, to_jwks(&[key]).to_string()).unwrap();
+        assert!(source.keys().await.is_err());
+
+        source.cache.lock().await.retry_at = 0;
+        assert_eq!(source.keys().await.unwrap()[0].kid, "0");
+
+        // Stale keys outlive a failed fetch.
+        std::fs::remove_file(&path).unwrap();
+        source.cache.lock().await.fetched_at = 0;
+        assert_eq!(source.keys().await.unwrap()[0].kid, "0");
+        assert_ne!(source.cache.lock().await.retry_at, 0);
+    }
+}
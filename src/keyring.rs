@@ -0,0 +1,313 @@
+//! Token signing keys.
+//!
+//! Keys are persisted as a json file, so that they survive a restart and can be rotated by the
+//! `pool key` command while the server is running. Each key has a `kid`. Tokens are signed with
+//! the current key and verified against any key that has not been retired.
+//!
+//! Keys are either HS512 secrets, which only suit pools that share them, or Ed25519 keys, whose
+//! public halves are published as JWKS so that other pools can verify tokens without holding a
+//! secret. Public keys of a central issuer may be trusted as well, see `jwks`.
+//!
+//! Without a key file, the configured `key`, if any, is the only key, with kid `default`.
+//!
+//! Parsed keys are cached, and the file is only read again once its mtime changes.
+use std::{io, path::PathBuf, time::SystemTime};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{
+    jwks::{JwksSource, PublicKey},
+    util,
+};
+
+const DEFAULT_KID: &str = "default";
+
+/// Keys parsed from the key file as of its mtime `modified`, `None` without a key file.
+struct KeyCache {
+    modified: Option<SystemTime>,
+    key_v: Vec<SigningKey>,
+}
+
+// Public
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyState {
+    /// Signs new tokens and verifies tokens.
+    Current,
+    /// Only verifies tokens.
+    Active,
+    /// Neither signs nor verifies tokens.
+    Retired,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum KeyAlg {
+    #[default]
+    #[serde(rename = "HS512")]
+    Hs512,
+    #[serde(rename = "EdDSA")]
+    EdDsa,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SigningKey {
+    pub kid: String,
+    #[serde(default)]
+    pub alg: KeyAlg,
+    /// Hex HMAC key, or hex Ed25519 seed.
+    pub secret: String,
+    pub state: KeyState,
+    pub created_at: u64,
+}
+
+impl SigningKey {
+    /// Get the public half of an Ed25519 key.
+    pub fn public_key(&self) -> Option<PublicKey> {
+        if self.alg != KeyAlg::EdDsa {
+            return None;
+        }
+        let seed: [u8; 32] = util::hex2byte_v(&self.secret).try_into().ok()?;
+        Some(PublicKey {
+            kid: self.kid.clone(),
+            key: ed25519_dalek::SigningKey::from_bytes(&seed).verifying_key(),
+            is_foreign: false,
+        })
+    }
+}
+
+pub struct KeyStore {
+    path: PathBuf,
+    fallback: String,
+    jwks: JwksSource,
+    cache: Mutex<Option<KeyCache>>,
+}
+
+impl KeyStore {
+    /// Keys are persisted at `path`. `fallback` is the hex key used while there is no key file,
+    /// and `jwks` is the file or URL of trusted public keys, both may be empty.
+    pub fn new(path: impl Into<PathBuf>, fallback: &str, jwks: &str) -> Self {
+        Self {
+            path: path.into(),
+            fallback: fallback.to_string(),
+            jwks: JwksSource::new(jwks),
+            cache: Mutex::new(None),
+        }
+    }
+
+    pub async fn list(&self) -> io::Result<Vec<SigningKey>> {
+        let mut cache = self.cache.lock().await;
+        self.load(&mut cache).await
+    }
+
+    /// Get the key that signs new tokens.
+    pub async fn current(&self) -> io::Result<SigningKey> {
+        self.list()
+            .await?
+            .into_iter()
+            .find(|key| key.state == KeyState::Current)
+            .ok_or(io::Error::new(io::ErrorKind::NotFound, "no current key"))
+    }
+
+    /// Get the keys that verify tokens.
+    pub async fn verifying(&self) -> io::Result<Vec<SigningKey>> {
+        Ok(self
+            .list()
+            .await?
+            .into_iter()
+            .filter(|key| key.state != KeyState::Retired)
+            .collect())
+    }
+
+    /// Get the public keys of own Ed25519 keys that verify tokens.
+    pub async fn own_public_keys(&self) -> io::Result<Vec<PublicKey>> {
+        Ok(self
+            .verifying()
+            .await?
+            .iter()
+            .filter_map(|key| key.public_key())
+            .collect())
+    }
+
+    /// Get all public keys that verify tokens, own and trusted ones.
+    pub async fn public_keys(&self) -> io::Result<Vec<PublicKey>> {
+        let mut key_v = self.own_public_keys().await?;
+        key_v.extend(self.jwks.keys().await?);
+        Ok(key_v)
+    }
+
+    /// Generate an active key. It verifies tokens at once, and signs them once promoted.
+    pub async fn generate(&self, alg: KeyAlg) -> io::Result<SigningKey> {
+        let mut cache = self.cache.lock().await;
+        let mut key_v = self.load(&mut cache).await?;
+        let key = SigningKey {
+            kid: util::byte_v2hex(&rand::random::<[u8; 8]>()),
+            alg,
+            secret: util::byte_v2hex(&rand::random::<[u8; 32]>()),
+            state: KeyState::Active,
+            created_at: util::timestamp(),
+        };
+        key_v.push(key.clone());
+        self.save(&mut cache, &key_v).await?;
+        Ok(key)
+    }
+
+    /// Make key `kid` the current key. The previous current key stays active.
+    pub async fn promote(&self, kid: &str) -> io::Result<()> {
+        let mut cache = self.cache.lock().await;
+        let mut key_v = self.load(&mut cache).await?;
+        match key_v.iter().find(|key| key.kid == kid) {
+            Some(key) if key.state == KeyState::Retired => {
+                return Err(io::Error::other(format!("{kid} is retired")))
+            }
+            Some(_) => (),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no key {kid}"),
+                ))
+            }
+        }
+        for key in &mut key_v {
+            if key.kid == kid {
+                key.state = KeyState::Current;
+            } else if key.state == KeyState::Current {
+                key.state = KeyState::Active;
+            }
+        }
+        self.save(&mut cache, &key_v).await
+    }
+
+    /// Retire key `kid`, so that tokens signed with it are rejected.
+    pub async fn retire(&self, kid: &str) -> io::Result<()> {
+        let mut cache = self.cache.lock().await;
+        let mut key_v = self.load(&mut cache).await?;
+        let key = key_v
+            .iter_mut()
+            .find(|key| key.kid == kid)
+            .ok_or(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no key {kid}"),
+            ))?;
+        if key.state == KeyState::Current {
+            return Err(io::Error::other(format!(
+                "{kid} is current, promote another key first"
+            )));
+        }
+        key.state = KeyState::Retired;
+        self.save(&mut cache, &key_v).await
+    }
+
+    async fn modified(&self) -> io::Result<Option<SystemTime>> {
+        util::not_found_as_none(tokio::fs::metadata(&self.path).await)?
+            .map(|metadata| metadata.modified())
+            .transpose()
+    }
+
+    /// Get the keys from `cache`, unless the key file changed since they were read.
+    async fn load(&self, cache: &mut Option<KeyCache>) -> io::Result<Vec<SigningKey>> {
+        let modified = self.modified().await?;
+        if let Some(cache) = cache.as_ref().filter(|cache| cache.modified == modified) {
+            return Ok(cache.key_v.clone());
+        }
+        let key_v = self.read().await?;
+        *cache = Some(KeyCache {
+            modified,
+            key_v: key_v.clone(),
+        });
+        Ok(key_v)
+    }
+
+    async fn read(&self) -> io::Result<Vec<SigningKey>> {
+        match util::not_found_as_none(tokio::fs::read(&self.path).await)? {
+            Some(content) => serde_json::from_slice(&content).map_err(io::Error::other),
+            None if self.fallback.is_empty() => Ok(Vec::new()),
+            None => Ok(vec![SigningKey {
+                kid: DEFAULT_KID.to_string(),
+                alg: KeyAlg::Hs512,
+                secret: self.fallback.clone(),
+                state: KeyState::Current,
+                created_at: 0,
+            }]),
+        }
+    }
+
+    async fn save(&self, cache: &mut Option<KeyCache>, key_v: &[SigningKey]) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let temp_path = self.path.with_extension("json.temp");
+        let mut options = tokio::fs::OpenOptions::new();
+        options.create(true).write(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut f = options.open(&temp_path).await?;
+        tokio::io::AsyncWriteExt::write_all(
+            &mut f,
+            &serde_json::to_vec_pretty(key_v).map_err(io::Error::other)?,
+        )
+        .await?;
+        f.sync_all().await?;
+        tokio::fs::rename(&temp_path, &self.path).await?;
+        *cache = Some(KeyCache {
+            modified: self.modified().await?,
+            key_v: key_v.to_vec(),
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeyAlg, KeyState, KeyStore};
+
+    #[tokio::test]
+    async fn test() {
+        let dir = std::env::temp_dir().join(format!("pool_test_keyring_{}", rand::random::<u64>()));
+        let store = KeyStore::new(dir.join("keys.json"), "ab", "");
+        let current = store.current().await.unwrap();
+        assert_eq!(
+            (current.kid.as_str(), current.secret.as_str()),
+            ("default", "ab")
+        );
+
+        let key = store.generate(KeyAlg::Hs512).await.unwrap();
+        assert_eq!(store.current().await.unwrap().kid, "default");
+        assert_eq!(store.verifying().await.unwrap().len(), 2);
+
+        // Keys survive a restart.
+        let store = KeyStore::new(dir.join("keys.json"), "", "");
+        assert!(store.retire(&key.kid).await.is_ok());
+        assert!(store.promote(&key.kid).await.is_err());
+        let key = store.generate(KeyAlg::EdDsa).await.unwrap();
+        assert_eq!(store.own_public_keys().await.unwrap()[0].kid, key.kid);
+        store.promote(&key.kid).await.unwrap();
+        assert_eq!(store.current().await.unwrap().kid, key.kid);
+        assert!(store.retire(&key.kid).await.is_err());
+        store.retire("default").await.unwrap();
+
+        let key_v = store.list().await.unwrap();
+        assert_eq!(key_v.len(), 3);
+        assert_eq!(key_v[0].state, KeyState::Retired);
+        assert_eq!(store.verifying().await.unwrap().len(), 1);
+
+        // Parsed keys are cached until the file changes, as when `pool key` rotates keys.
+        let path = dir.join("keys.json");
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        let command = KeyStore::new(&path, "", "");
+        let key = command.generate(KeyAlg::Hs512).await.unwrap();
+        let file = std::fs::File::options().append(true).open(&path).unwrap();
+        file.set_modified(modified).unwrap();
+        assert_eq!(store.verifying().await.unwrap().len(), 1);
+        file.set_modified(modified + std::time::Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(store.verifying().await.unwrap()[1].kid, key.kid);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Without a key file nor a fallback there is no key.
+        let store = KeyStore::new(dir.join("keys.json"), "", "");
+        assert!(store.list().await.unwrap().is_empty());
+        assert!(store.current().await.is_err());
+    }
+}
@@ -1,4 +1,19 @@
-pub mod server;
-pub mod err;
+pub mod acl;
+pub mod api_key;
 pub mod connector;
+pub mod drop_box;
+pub mod err;
+pub mod file;
+pub mod gc;
+pub mod graph;
+pub mod jwks;
+pub mod keyring;
+pub mod refresh_token;
+pub mod revocation;
+pub mod role;
+pub mod server;
+pub mod session;
+pub mod share_link;
+pub mod storage;
+pub mod user;
 pub mod util;
@@ -1,11 +1,18 @@
 use std::{io, sync::Arc, time::Duration};
 
 use earth::AsConfig;
-use pool::{connector, server};
 use edge_lib::{
     data::{AsDataManager, MemDataManager, RecDataManager},
     EdgeEngine, ScriptTree,
 };
+use pool::{
+    connector, gc, graph,
+    keyring::{KeyAlg, KeyStore},
+    role, server,
+    session::SessionStore,
+    storage::{self, AsBlobStore},
+    util,
+};
 use serde::{Deserialize, Serialize};
 use tokio::time;
 
@@ -19,6 +26,24 @@ struct Config {
     log_level: String,
     key: String,
     moon_servers: Vec<String>,
+    admins: Vec<String>,
+    storage_dir: String,
+    storage_backend: String,
+    session_ttl: u64,
+    max_upload_size: u64,
+    token_ttl: u64,
+    token_leeway: u64,
+    refresh_ttl: u64,
+    token_issuer: String,
+    token_audience: String,
+    token_jwks: String,
+    temp_ttl: u64,
+    gc_interval: u64,
+    s3_endpoint: String,
+    s3_bucket: String,
+    s3_region: String,
+    s3_access_key: String,
+    s3_secret_key: String,
 }
 
 impl Default for Config {
@@ -32,13 +57,97 @@ impl Default for Config {
             log_level: "INFO".to_string(),
             key: format!(""),
             moon_servers: Vec::new(),
+            admins: Vec::new(),
+            storage_dir: ".".to_string(),
+            storage_backend: "fs".to_string(),
+            session_ttl: 24 * 3600,
+            max_upload_size: 4 << 30,
+            token_ttl: 3600,
+            token_leeway: 60,
+            refresh_ttl: 30 * 24 * 3600,
+            token_issuer: String::new(),
+            token_audience: String::new(),
+            token_jwks: String::new(),
+            temp_ttl: 24 * 3600,
+            gc_interval: 3600,
+            s3_endpoint: String::new(),
+            s3_bucket: String::new(),
+            s3_region: "us-east-1".to_string(),
+            s3_access_key: String::new(),
+            s3_secret_key: String::new(),
+        }
+    }
+}
+
+fn open_store(config: &Config) -> io::Result<Arc<dyn AsBlobStore>> {
+    match config.storage_backend.as_str() {
+        "fs" => {
+            storage::migrate(std::path::Path::new(&config.storage_dir))?;
+            Ok(Arc::new(storage::FsBlobStore::new(&config.storage_dir)))
+        }
+        "mem" => Ok(Arc::new(storage::MemBlobStore::new())),
+        "s3" => Ok(Arc::new(storage::S3BlobStore::new(
+            storage::S3Option {
+                endpoint: config.s3_endpoint.clone(),
+                bucket: config.s3_bucket.clone(),
+                region: config.s3_region.clone(),
+                access_key: config.s3_access_key.clone(),
+                secret_key: config.s3_secret_key.clone(),
+            },
+            &config.storage_dir,
+        )?)),
+        backend => Err(io::Error::other(format!(
+            "unknown storage_backend: {backend}"
+        ))),
+    }
+}
+
+/// `pool key list|new [eddsa]|promote <kid>|retire <kid>`
+async fn key_command(keys: &KeyStore, arg_v: &[String]) -> io::Result<()> {
+    let arg_v: Vec<&str> = arg_v.iter().map(|s| s.as_str()).collect();
+    match arg_v.as_slice() {
+        ["list"] => {
+            for key in keys.list().await? {
+                let alg = match key.alg {
+                    KeyAlg::Hs512 => "HS512",
+                    KeyAlg::EdDsa => "EdDSA",
+                };
+                println!(
+                    "{}\t{alg}\t{}\t{}",
+                    key.kid,
+                    format!("{:?}", key.state).to_lowercase(),
+                    key.created_at
+                );
+            }
+        }
+        ["new"] => println!("{}", keys.generate(KeyAlg::Hs512).await?.kid),
+        ["new", "eddsa"] => println!("{}", keys.generate(KeyAlg::EdDsa).await?.kid),
+        ["promote", kid] => keys.promote(kid).await?,
+        ["retire", kid] => keys.retire(kid).await?,
+        _ => {
+            return Err(io::Error::other(
+                "usage: pool key list|new [eddsa]|promote <kid>|retire <kid>",
+            ))
         }
     }
+    Ok(())
 }
 
 fn main() -> io::Result<()> {
     let mut arg_v: Vec<String> = std::env::args().collect();
     arg_v.remove(0);
+    let command_v: Vec<String> = match arg_v.first().map(|s| s.as_str()) {
+        Some("gc") => arg_v.drain(..1).collect(),
+        Some("key") => {
+            let n = match arg_v.get(1).map(|s| s.as_str()) {
+                Some("promote") | Some("retire") => 3,
+                Some("new") if arg_v.get(2).map(|s| s.as_str()) == Some("eddsa") => 3,
+                _ => 2,
+            };
+            arg_v.drain(..std::cmp::min(n, arg_v.len())).collect()
+        }
+        _ => Vec::new(),
+    };
     let file_name = if !arg_v.is_empty() && !arg_v[0].starts_with("--") {
         arg_v.remove(0)
     } else {
@@ -54,11 +163,55 @@ fn main() -> io::Result<()> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(&config.log_level))
         .init();
 
+    let store = open_store(&config)?;
+    let sessions = Arc::new(SessionStore::new(
+        std::path::Path::new(&config.storage_dir).join("sessions"),
+        config.session_ttl,
+    ));
+    let keys = Arc::new(KeyStore::new(
+        std::path::Path::new(&config.storage_dir).join("keys.json"),
+        &config.key,
+        &config.token_jwks,
+    ));
+    let collector = gc::TempCollector::new(
+        store.clone(),
+        sessions.clone(),
+        config.temp_ttl,
+        config.gc_interval,
+    );
+
+    if command_v.first().map(|s| s.as_str()) == Some("key") {
+        return tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(key_command(&keys, &command_v[1..]));
+    }
+    if command_v.first().map(|s| s.as_str()) == Some("gc") {
+        return tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(async {
+                collector.collect().await?;
+                Ok(())
+            });
+    }
+
     tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .worker_threads(config.thread_num as usize)
         .build()?
         .block_on(async {
+            // Tokens are issued by and for this pool unless a central issuer is configured.
+            let token_issuer = if config.token_issuer.is_empty() {
+                config.name.clone()
+            } else {
+                config.token_issuer.clone()
+            };
+            let token_audience = if config.token_audience.is_empty() {
+                config.name.clone()
+            } else {
+                config.token_audience.clone()
+            };
             let dm = RecDataManager::new(Arc::new(MemDataManager::new()));
             let mut edge_engine = EdgeEngine::new(dm.divide());
             // config.ip, config.port, config.name
@@ -67,7 +220,15 @@ fn main() -> io::Result<()> {
                 format!("root->ip = = {} _", config.ip),
                 format!("root->port = = {} _", config.port),
                 format!("root->path = = {} _", format!("/{}", config.name)),
-                format!("root->key = = {} _", config.key),
+                format!("root->max_upload_size = = {} _", config.max_upload_size),
+                format!("root->token_ttl = = {} _", config.token_ttl),
+                format!("root->token_leeway = = {} _", config.token_leeway),
+                format!("root->refresh_ttl = = {} _", config.refresh_ttl),
+                format!("root->token_issuer = = {} _", util::escape(&token_issuer)),
+                format!(
+                    "root->token_audience = = {} _",
+                    util::escape(&token_audience)
+                ),
             ]
             .join("\n");
             let option_script = config
@@ -84,9 +245,24 @@ fn main() -> io::Result<()> {
                 })
                 .await?;
             edge_engine.commit().await?;
+            let journal = Arc::new(
+                graph::Journal::open(
+                    dm.divide(),
+                    &std::path::Path::new(&config.storage_dir).join("graph.journal"),
+                )
+                .await?,
+            );
+            for email in &config.admins {
+                if role::find(dm.divide(), email).await? != role::Role::Admin {
+                    role::assign(dm.divide(), &journal, email, role::Role::Admin).await?;
+                }
+            }
 
             tokio::spawn(connector::HttpConnector::new(dm.divide()).run());
-            tokio::spawn(server::HttpServer::new(dm.divide()).run());
+            tokio::spawn(collector.run());
+            tokio::spawn(
+                server::HttpServer::new(dm.divide(), journal, store, sessions, keys).run(),
+            );
             loop {
                 log::info!("alive");
                 time::sleep(Duration::from_secs(10)).await;
@@ -0,0 +1,225 @@
+//! Refresh tokens in the edge graph.
+//!
+//! A login opens a session, identified by the id of its refresh token and carried by access
+//! tokens as `jti`. A refresh token is presented as `{id}.{secret}` and is stored like an API key,
+//! as a `root->refresh_token` node with the sha256 of its secret. It is rotated on every use: the
+//! new token keeps the id, so that the session survives, but gets a new secret. Tokens are
+//! indexed by id and by owner.
+use std::{io, sync::Arc};
+
+use edge_lib::{data::AsDataManager, Path};
+
+use crate::{
+    api_key::{hash_secret, is_equal},
+    graph::{self, get_field, Journal},
+    util,
+};
+
+async fn load_all(
+    dm: Arc<dyn AsDataManager>,
+    node_v: Vec<String>,
+) -> io::Result<Vec<(String, RefreshTokenRecord)>> {
+    let mut token_v = Vec::with_capacity(node_v.len());
+    for node in node_v {
+        let record = RefreshTokenRecord::load(dm.as_ref(), &node).await?;
+        token_v.push((node, record));
+    }
+    Ok(token_v)
+}
+
+// Public
+#[derive(Debug, Clone)]
+pub struct RefreshTokenRecord {
+    /// Id of the session.
+    pub id: String,
+    /// sha256 of the secret as lowercase hex.
+    pub secret_hash: String,
+    pub owner: String,
+    pub expires_at: u64,
+    pub created_at: u64,
+}
+
+impl RefreshTokenRecord {
+    /// Generate a token of a new session of `owner`, valid for `ttl` seconds, returning its record
+    /// and the token to hand out.
+    pub fn generate(owner: &str, ttl: u64) -> (Self, String) {
+        Self::rotate(&util::byte_v2hex(&rand::random::<[u8; 8]>()), owner, ttl)
+    }
+
+    /// Generate the next token of session `id`.
+    pub fn rotate(id: &str, owner: &str, ttl: u64) -> (Self, String) {
+        let secret = util::byte_v2hex(&rand::random::<[u8; 32]>());
+        let now = util::timestamp();
+        let record = Self {
+            id: id.to_string(),
+            secret_hash: hash_secret(&secret),
+            owner: owner.to_string(),
+            expires_at: now + ttl,
+            created_at: now,
+        };
+        (record, format!("{id}.{secret}"))
+    }
+
+    /// Load the record of token node `node`.
+    pub async fn load(dm: &dyn AsDataManager, node: &str) -> io::Result<Self> {
+        Ok(Self {
+            id: get_field(dm, node, "id").await?,
+            secret_hash: get_field(dm, node, "secret_hash").await?,
+            owner: util::unescape(&get_field(dm, node, "owner").await?),
+            expires_at: get_field(dm, node, "expires_at")
+                .await?
+                .parse()
+                .unwrap_or(0),
+            created_at: get_field(dm, node, "created_at")
+                .await?
+                .parse()
+                .unwrap_or(0),
+        })
+    }
+
+    /// Write this record into the graph as a new `root->refresh_token` node.
+    pub async fn insert(&self, dm: Arc<dyn AsDataManager>, journal: &Journal) -> io::Result<()> {
+        let script = self.line_v(&graph::new_node()).join("\n");
+        graph::execute(dm, journal, script, "insert refresh_token").await
+    }
+
+    /// Script lines writing this record as `root->refresh_token` node `node`, with its indexes.
+    fn line_v(&self, node: &str) -> Vec<String> {
+        let mut line_v = vec![
+            format!("$->$refresh_token = = {node} _"),
+            format!("$->$refresh_token->id = = {} _", self.id),
+            format!("$->$refresh_token->secret_hash = = {} _", self.secret_hash),
+            format!(
+                "$->$refresh_token->owner = = {} _",
+                util::escape(&self.owner)
+            ),
+            format!("$->$refresh_token->expires_at = = {} _", self.expires_at),
+            format!("$->$refresh_token->created_at = = {} _", self.created_at),
+            format!("root->refresh_token += = $->$refresh_token _"),
+        ];
+        line_v.extend(graph::index_line_v(
+            "refresh_token_by_id",
+            &self.id,
+            "refresh_token",
+        ));
+        line_v.extend(graph::index_line_v(
+            "refresh_token_by_owner",
+            &self.owner,
+            "refresh_token",
+        ));
+        line_v
+    }
+}
+
+/// List all token records with their nodes.
+pub async fn list(dm: Arc<dyn AsDataManager>) -> io::Result<Vec<(String, RefreshTokenRecord)>> {
+    let node_v = dm.get(&Path::from_str("root->refresh_token")).await?;
+    load_all(dm, node_v).await
+}
+
+/// List the token records of `owner` with their nodes.
+pub async fn list_by_owner(
+    dm: Arc<dyn AsDataManager>,
+    owner: &str,
+) -> io::Result<Vec<(String, RefreshTokenRecord)>> {
+    let node_v = graph::lookup(dm.as_ref(), "refresh_token_by_owner", owner).await?;
+    load_all(dm, node_v).await
+}
+
+/// Find the node and record of `token`, or `None` if it is not a valid, unexpired token.
+pub async fn verify(
+    dm: Arc<dyn AsDataManager>,
+    token: &str,
+    now: u64,
+) -> io::Result<Option<(String, RefreshTokenRecord)>> {
+    let (id, secret) = match token.trim().split_once('.') {
+        Some(r) => r,
+        None => return Ok(None),
+    };
+    let secret_hash = hash_secret(secret);
+    let node_v = graph::lookup(dm.as_ref(), "refresh_token_by_id", id).await?;
+    Ok(load_all(dm, node_v)
+        .await?
+        .into_iter()
+        .next()
+        .filter(|(_, record)| is_equal(&record.secret_hash, &secret_hash))
+        .filter(|(_, record)| now < record.expires_at))
+}
+
+/// Remove every token of session `id`, ending the session.
+pub async fn remove_session(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    id: &str,
+) -> io::Result<()> {
+    for node in graph::lookup(dm.as_ref(), "refresh_token_by_id", id).await? {
+        remove(dm.clone(), journal, &node).await?;
+    }
+    Ok(())
+}
+
+/// Remove token node `node` from `root->refresh_token` and its indexes.
+pub async fn remove(dm: Arc<dyn AsDataManager>, journal: &Journal, node: &str) -> io::Result<()> {
+    let record = RefreshTokenRecord::load(dm.as_ref(), node).await?;
+    let index_v = [
+        ("refresh_token_by_id", record.id.as_str()),
+        ("refresh_token_by_owner", record.owner.as_str()),
+    ];
+    graph::remove_node(dm, journal, "refresh_token", node, &index_v).await
+}
+
+/// Scripts recreating every token record on its node.
+pub async fn snapshot(dm: Arc<dyn AsDataManager>) -> io::Result<Vec<String>> {
+    Ok(list(dm)
+        .await?
+        .into_iter()
+        .map(|(node, record)| record.line_v(&node).join("\n"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use edge_lib::data::{AsDataManager, MemDataManager};
+
+    use crate::{graph::Journal, util};
+
+    use super::{list, list_by_owner, remove, remove_session, verify, RefreshTokenRecord};
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test() {
+        let dm = MemDataManager::new();
+        let journal = Journal::new();
+        let (record, token) = RefreshTokenRecord::generate("a@b.c", 60);
+        record.insert(dm.divide(), &journal).await.unwrap();
+
+        let now = util::timestamp();
+        let (node, found) = verify(dm.divide(), &token, now).await.unwrap().unwrap();
+        assert_eq!(found.owner, "a@b.c");
+        assert!(verify(dm.divide(), &token, now + 60)
+            .await
+            .unwrap()
+            .is_none());
+
+        // The rotated token keeps the session but not the secret.
+        let (next, next_token) = RefreshTokenRecord::rotate(&found.id, &found.owner, 60);
+        remove(dm.divide(), &journal, &node).await.unwrap();
+        next.insert(dm.divide(), &journal).await.unwrap();
+        assert!(verify(dm.divide(), &token, now).await.unwrap().is_none());
+        let (_, found) = verify(dm.divide(), &next_token, now)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.id, record.id);
+        assert_eq!(list(dm.divide()).await.unwrap().len(), 1);
+        assert_eq!(list_by_owner(dm.divide(), "a@b.c").await.unwrap().len(), 1);
+
+        remove_session(dm.divide(), &journal, &record.id)
+            .await
+            .unwrap();
+        assert!(verify(dm.divide(), &next_token, now)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(list(dm.divide()).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,235 @@
+//! Revoked tokens in the edge graph.
+//!
+//! Every revocation is a `root->revoked` node. A node with a `jti` revokes the access tokens of
+//! one session, a node with a `sub` revokes every access token of a user issued until
+//! `revoked_at`, so that a stolen token or a removed user is cut off before the token expires.
+//! A node is pruned at its `expires_at`, once every token it cuts off has expired. Nodes are
+//! indexed by `jti` and by `sub`, so that checking a token does not load every revocation.
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    sync::Arc,
+};
+
+use edge_lib::{data::AsDataManager, Path};
+
+use crate::{
+    graph::{self, execute, get_field, Journal},
+    util,
+};
+
+// Public
+#[derive(Debug, Clone, Default)]
+pub struct Revocation {
+    /// Empty if this revokes a user.
+    pub jti: String,
+    /// Empty if this revokes a session.
+    pub sub: String,
+    pub revoked_at: u64,
+    pub expires_at: u64,
+}
+
+impl Revocation {
+    /// Load the revocation of node `node`.
+    pub async fn load(dm: &dyn AsDataManager, node: &str) -> io::Result<Self> {
+        Ok(Self {
+            jti: get_field(dm, node, "jti").await?,
+            sub: util::unescape(&get_field(dm, node, "sub").await?),
+            revoked_at: get_field(dm, node, "revoked_at")
+                .await?
+                .parse()
+                .unwrap_or(0),
+            expires_at: get_field(dm, node, "expires_at")
+                .await?
+                .parse()
+                .unwrap_or(0),
+        })
+    }
+
+    /// Write this revocation into the graph as a new `root->revoked` node.
+    pub async fn insert(&self, dm: Arc<dyn AsDataManager>, journal: &Journal) -> io::Result<()> {
+        let script = self.line_v(&graph::new_node()).join("\n");
+        execute(dm, journal, script, "insert revoked").await
+    }
+
+    /// Script lines writing this revocation as `root->revoked` node `node`, with its indexes.
+    fn line_v(&self, node: &str) -> Vec<String> {
+        let mut line_v = vec![format!("$->$revoked = = {node} _")];
+        if !self.jti.is_empty() {
+            line_v.push(format!("$->$revoked->jti = = {} _", self.jti));
+        }
+        if !self.sub.is_empty() {
+            line_v.push(format!(
+                "$->$revoked->sub = = {} _",
+                util::escape(&self.sub)
+            ));
+        }
+        line_v.push(format!("$->$revoked->revoked_at = = {} _", self.revoked_at));
+        line_v.push(format!("$->$revoked->expires_at = = {} _", self.expires_at));
+        line_v.push(format!("root->revoked += = $->$revoked _"));
+        line_v.extend(graph::index_line_v("revoked_by_jti", &self.jti, "revoked"));
+        line_v.extend(graph::index_line_v("revoked_by_sub", &self.sub, "revoked"));
+        line_v
+    }
+}
+
+/// Revocations as checked by `parse_token`.
+#[derive(Debug, Default)]
+pub struct RevocationList {
+    jti_set: HashSet<String>,
+    sub_mp: HashMap<String, u64>,
+}
+
+impl RevocationList {
+    /// Load the revocations of session `jti` and of user `sub`, the only ones a token of them
+    /// is checked against.
+    pub async fn load(dm: Arc<dyn AsDataManager>, jti: &str, sub: &str) -> io::Result<Self> {
+        let mut revoked = Self::default();
+        let mut node_v = graph::lookup(dm.as_ref(), "revoked_by_jti", jti).await?;
+        node_v.extend(graph::lookup(dm.as_ref(), "revoked_by_sub", sub).await?);
+        for node in node_v {
+            revoked.add(Revocation::load(dm.as_ref(), &node).await?);
+        }
+        Ok(revoked)
+    }
+
+    pub fn add(&mut self, revocation: Revocation) {
+        if !revocation.jti.is_empty() {
+            self.jti_set.insert(revocation.jti);
+        }
+        if !revocation.sub.is_empty() {
+            let revoked_at = self.sub_mp.entry(revocation.sub).or_default();
+            *revoked_at = std::cmp::max(*revoked_at, revocation.revoked_at);
+        }
+    }
+
+    /// Whether a token of session `jti` issued to `sub` at `iat` is revoked.
+    pub fn is_revoked(&self, jti: &str, sub: &str, iat: u64) -> bool {
+        (!jti.is_empty() && self.jti_set.contains(jti))
+            || self
+                .sub_mp
+                .get(sub)
+                .is_some_and(|revoked_at| iat <= *revoked_at)
+    }
+}
+
+/// List all revocations with their nodes.
+pub async fn list(dm: Arc<dyn AsDataManager>) -> io::Result<Vec<(String, Revocation)>> {
+    let node_v = dm.get(&Path::from_str("root->revoked")).await?;
+    let mut revocation_v = Vec::with_capacity(node_v.len());
+    for node in node_v {
+        let revocation = Revocation::load(dm.as_ref(), &node).await?;
+        revocation_v.push((node, revocation));
+    }
+    Ok(revocation_v)
+}
+
+/// Revoke the access tokens of session `jti`, which all expire by `expires_at`.
+pub async fn revoke_session(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    jti: &str,
+    expires_at: u64,
+) -> io::Result<()> {
+    prune(dm.clone(), journal, util::timestamp()).await?;
+    Revocation {
+        jti: jti.to_string(),
+        revoked_at: util::timestamp(),
+        expires_at,
+        ..Default::default()
+    }
+    .insert(dm, journal)
+    .await
+}
+
+/// Revoke the access tokens issued to `sub` until now, which all expire by `expires_at`.
+pub async fn revoke_subject(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    sub: &str,
+    expires_at: u64,
+) -> io::Result<()> {
+    prune(dm.clone(), journal, util::timestamp()).await?;
+    Revocation {
+        sub: sub.to_string(),
+        revoked_at: util::timestamp(),
+        expires_at,
+        ..Default::default()
+    }
+    .insert(dm, journal)
+    .await
+}
+
+/// Remove the revocations that expired by `now`, returning how many were removed.
+pub async fn prune(dm: Arc<dyn AsDataManager>, journal: &Journal, now: u64) -> io::Result<usize> {
+    let mut count = 0;
+    for (node, revocation) in list(dm.clone()).await? {
+        if now < revocation.expires_at {
+            continue;
+        }
+        let index_v = [
+            ("revoked_by_jti", revocation.jti.as_str()),
+            ("revoked_by_sub", revocation.sub.as_str()),
+        ];
+        graph::remove_node(dm.clone(), journal, "revoked", &node, &index_v).await?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Scripts recreating every revocation on its node.
+pub async fn snapshot(dm: Arc<dyn AsDataManager>) -> io::Result<Vec<String>> {
+    Ok(list(dm)
+        .await?
+        .into_iter()
+        .map(|(node, revocation)| revocation.line_v(&node).join("\n"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use edge_lib::data::{AsDataManager, MemDataManager};
+
+    use crate::{graph::Journal, util};
+
+    use super::{prune, revoke_session, revoke_subject, RevocationList};
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test() {
+        let dm = MemDataManager::new();
+        let journal = Journal::new();
+        let now = util::timestamp();
+        revoke_session(dm.divide(), &journal, "s0", now + 60)
+            .await
+            .unwrap();
+        revoke_subject(dm.divide(), &journal, "a@b.c", now + 60)
+            .await
+            .unwrap();
+
+        let revoked = RevocationList::load(dm.divide(), "s0", "b@b.c")
+            .await
+            .unwrap();
+        assert!(revoked.is_revoked("s0", "b@b.c", now));
+        let revoked = RevocationList::load(dm.divide(), "s1", "b@b.c")
+            .await
+            .unwrap();
+        assert!(!revoked.is_revoked("s1", "b@b.c", now));
+        let revoked = RevocationList::load(dm.divide(), "", "b@b.c")
+            .await
+            .unwrap();
+        assert!(!revoked.is_revoked("", "b@b.c", now));
+        let revoked = RevocationList::load(dm.divide(), "s1", "a@b.c")
+            .await
+            .unwrap();
+        assert!(revoked.is_revoked("s1", "a@b.c", now));
+        // Tokens issued after the revocation are valid again.
+        assert!(!revoked.is_revoked("s1", "a@b.c", now + 2));
+
+        assert_eq!(prune(dm.divide(), &journal, now + 59).await.unwrap(), 0);
+        assert_eq!(prune(dm.divide(), &journal, now + 60).await.unwrap(), 2);
+        let revoked = RevocationList::load(dm.divide(), "s0", "a@b.c")
+            .await
+            .unwrap();
+        assert!(!revoked.is_revoked("s0", "a@b.c", now));
+    }
+}
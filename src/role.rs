@@ -0,0 +1,218 @@
+//! Roles and scopes in the edge graph.
+//!
+//! Every role assignment is a `root->role` node with an `email` and a `role`. A user without an
+//! assignment is a writer. Each role grants a set of scopes, which tokens carry as the
+//! space-separated `scope` claim and routes check. Assignments are indexed by email under
+//! `root->role_by_email`.
+use std::{fmt::Display, io, str::FromStr, sync::Arc};
+
+use edge_lib::{data::AsDataManager, Path};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    graph::{self, execute, get_field, Journal},
+    util,
+};
+
+/// Script writing the assignment of `role` to `email` as `root->role` node `node`, with its index.
+fn script(node: &str, email: &str, role: Role) -> String {
+    [
+        &format!("$->$role = = {node} _"),
+        &format!("$->$role->email = = {} _", util::escape(email)),
+        &format!("$->$role->role = = {role} _"),
+        "root->role += = $->$role _",
+        &graph::index_line_v("role_by_email", email, "role").join("\n"),
+    ]
+    .join("\n")
+}
+
+// Public
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Admin,
+    #[default]
+    Writer,
+    Reader,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    Upload,
+    Download,
+    Delete,
+    /// Manage roles, and download files of any user.
+    Admin,
+}
+
+impl Role {
+    pub fn scope_v(&self) -> Vec<Scope> {
+        match self {
+            Role::Admin => vec![Scope::Upload, Scope::Download, Scope::Delete, Scope::Admin],
+            Role::Writer => vec![Scope::Upload, Scope::Download, Scope::Delete],
+            Role::Reader => vec![Scope::Download],
+        }
+    }
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Role::Admin => write!(f, "admin"),
+            Role::Writer => write!(f, "writer"),
+            Role::Reader => write!(f, "reader"),
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "admin" => Ok(Role::Admin),
+            "writer" => Ok(Role::Writer),
+            "reader" => Ok(Role::Reader),
+            _ => Err(io::Error::other(format!("unknown role: {s}"))),
+        }
+    }
+}
+
+impl Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Scope::Upload => write!(f, "upload"),
+            Scope::Download => write!(f, "download"),
+            Scope::Delete => write!(f, "delete"),
+            Scope::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "upload" => Ok(Scope::Upload),
+            "download" => Ok(Scope::Download),
+            "delete" => Ok(Scope::Delete),
+            "admin" => Ok(Scope::Admin),
+            _ => Err(io::Error::other(format!("unknown scope: {s}"))),
+        }
+    }
+}
+
+/// Format scopes as a `scope` claim.
+pub fn format_scope(scope_v: &[Scope]) -> String {
+    scope_v
+        .iter()
+        .map(|scope| scope.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Parse a `scope` claim, skipping unknown scopes.
+pub fn parse_scope(s: &str) -> Vec<Scope> {
+    s.split_whitespace()
+        .filter_map(|scope| scope.parse().ok())
+        .collect()
+}
+
+/// List all role assignments with their nodes.
+pub async fn list(dm: Arc<dyn AsDataManager>) -> io::Result<Vec<(String, String, Role)>> {
+    let node_v = dm.get(&Path::from_str("root->role")).await?;
+    let mut role_v = Vec::with_capacity(node_v.len());
+    for node in node_v {
+        let email = util::unescape(&get_field(dm.as_ref(), &node, "email").await?);
+        let role = get_field(dm.as_ref(), &node, "role").await?.parse()?;
+        role_v.push((node, email, role));
+    }
+    Ok(role_v)
+}
+
+/// Get the role of `email`.
+pub async fn find(dm: Arc<dyn AsDataManager>, email: &str) -> io::Result<Role> {
+    match graph::lookup(dm.as_ref(), "role_by_email", email)
+        .await?
+        .first()
+    {
+        Some(node) => get_field(dm.as_ref(), node, "role").await?.parse(),
+        None => Ok(Role::default()),
+    }
+}
+
+/// Assign `role` to `email`, replacing its current assignment.
+pub async fn assign(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    email: &str,
+    role: Role,
+) -> io::Result<()> {
+    unassign(dm.clone(), journal, email).await?;
+    let script = script(&graph::new_node(), email, role);
+    execute(dm, journal, script, "insert role").await
+}
+
+/// Remove the assignment of `email`, returning whether there was one.
+pub async fn unassign(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    email: &str,
+) -> io::Result<bool> {
+    let mut is_found = false;
+    for node in graph::lookup(dm.as_ref(), "role_by_email", email).await? {
+        let index_v = [("role_by_email", email)];
+        graph::remove_node(dm.clone(), journal, "role", &node, &index_v).await?;
+        is_found = true;
+    }
+    Ok(is_found)
+}
+
+/// Scripts recreating every role assignment on its node.
+pub async fn snapshot(dm: Arc<dyn AsDataManager>) -> io::Result<Vec<String>> {
+    Ok(list(dm)
+        .await?
+        .into_iter()
+        .map(|(node, email, role)| script(&node, &email, role))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use edge_lib::data::{AsDataManager, MemDataManager};
+
+    use crate::graph::Journal;
+
+    use super::{assign, find, format_scope, list, parse_scope, unassign, Role, Scope};
+
+    #[test]
+    fn test_scope() {
+        let scope = format_scope(&Role::Reader.scope_v());
+        assert_eq!(scope, "download");
+        assert_eq!(
+            parse_scope("upload  admin unknown"),
+            vec![Scope::Upload, Scope::Admin]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test() {
+        let dm = MemDataManager::new();
+        let journal = Journal::new();
+        assert_eq!(find(dm.divide(), "a@b.c").await.unwrap(), Role::Writer);
+
+        assign(dm.divide(), &journal, "a@b.c", Role::Reader)
+            .await
+            .unwrap();
+        assign(dm.divide(), &journal, "a@b.c", Role::Admin)
+            .await
+            .unwrap();
+        assert_eq!(find(dm.divide(), "a@b.c").await.unwrap(), Role::Admin);
+        assert_eq!(list(dm.divide()).await.unwrap().len(), 1);
+
+        assert!(unassign(dm.divide(), &journal, "a@b.c").await.unwrap());
+        assert_eq!(find(dm.divide(), "a@b.c").await.unwrap(), Role::Writer);
+    }
+}
@@ -5,96 +5,583 @@ mod service;
 use std::{io, sync::Arc};
 
 use axum::{
+    async_trait,
     body::Body,
-    extract::{Query, State},
-    http::{HeaderMap, StatusCode},
-    response::Response,
+    extract::{FromRequestParts, Path, Query, State},
+    http::{header, request::Parts, HeaderMap, Method, StatusCode},
+    response::{IntoResponse, Response},
     routing, Json, Router,
 };
 use edge_lib::{data::AsDataManager, EdgeEngine, ScriptTree};
+use tokio_util::io::ReaderStream;
 
-use crate::err;
+use crate::{
+    err, graph::Journal, keyring::KeyStore, session::SessionStore, storage::AsBlobStore, util,
+};
+
+const DOWNLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Clone)]
+struct HttpState {
+    dm: Arc<dyn AsDataManager>,
+    journal: Arc<Journal>,
+    store: Arc<dyn AsBlobStore>,
+    sessions: Arc<SessionStore>,
+    keys: Arc<KeyStore>,
+}
+
+/// Authenticates the request, see `service::authenticate`.
+#[async_trait]
+impl FromRequestParts<HttpState> for crypto::User {
+    type Rejection = err::Error;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &HttpState,
+    ) -> Result<Self, Self::Rejection> {
+        service::authenticate(state.dm.divide(), &state.keys, &parts.headers).await
+    }
+}
+
+/// Set the access token as cookie too, for browsers.
+fn login_response(info: service::LoginInfo) -> Response<Body> {
+    let cookie = format!(
+        "token={}; Path=/; Max-Age={}; HttpOnly; SameSite=Strict",
+        info.access_token, info.expires_in
+    );
+    ([(header::SET_COOKIE, cookie)], Json(info)).into_response()
+}
+
+fn stream_response(ds: service::DataStream) -> Response<Body> {
+    let builder = Response::builder()
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::ETAG, format!("\"{}\"", ds.key))
+        .header(header::CONTENT_LENGTH, ds.end - ds.start);
+    let body = Body::from_stream(ReaderStream::with_capacity(ds.reader, DOWNLOAD_CHUNK_SIZE));
+    if ds.start == 0 && ds.end == ds.length {
+        builder.status(StatusCode::OK).body(body).unwrap()
+    } else {
+        builder
+            .header(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", ds.start, ds.end - 1, ds.length),
+            )
+            .status(StatusCode::PARTIAL_CONTENT)
+            .body(body)
+            .unwrap()
+    }
+}
+
+async fn http_register(
+    State(state): State<HttpState>,
+    Json(auth): Json<crypto::Auth>,
+) -> err::Result<impl IntoResponse> {
+    let s = service::register(state.dm.divide(), &state.journal, auth).await?;
+    Ok((StatusCode::CREATED, s))
+}
+
+async fn http_login(
+    State(state): State<HttpState>,
+    Json(auth): Json<crypto::Auth>,
+) -> err::Result<impl IntoResponse> {
+    let info = service::login(state.dm.divide(), &state.journal, &state.keys, auth).await?;
+    Ok(login_response(info))
+}
+
+async fn http_refresh(
+    State(state): State<HttpState>,
+    Json(rr): Json<service::RefreshRequest>,
+) -> err::Result<impl IntoResponse> {
+    let info = service::refresh(state.dm.divide(), &state.journal, &state.keys, rr).await?;
+    Ok(login_response(info))
+}
+
+async fn http_logout(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+    Query(lr): Query<service::LogoutRequest>,
+) -> err::Result<impl IntoResponse> {
+    let s = service::logout(state.dm.divide(), &state.journal, &auth, lr).await?;
+    Ok((
+        [(
+            header::SET_COOKIE,
+            "token=; Path=/; Max-Age=0; HttpOnly; SameSite=Strict",
+        )],
+        s,
+    ))
+}
+
+async fn http_jwks(State(state): State<HttpState>) -> err::Result<impl IntoResponse> {
+    let jwks = service::jwks(&state.keys).await?;
+    Ok(Json(jwks))
+}
 
 async fn http_upload(
-    hm: HeaderMap,
-    State(dm): State<Arc<dyn AsDataManager>>,
+    auth: crypto::User,
+    State(state): State<HttpState>,
     Json(ds): Json<service::DataSlice>,
-) -> Response<Body> {
-    match service::upload(dm.divide(), &hm, ds).await {
-        Ok(s) => Response::builder()
-            .status(StatusCode::OK)
-            .body(Body::from(s))
-            .unwrap(),
-        Err(e) => {
-            log::warn!("when http_execute:\n{e}");
-            match e {
-                err::Error::Other(msg) => Response::builder()
-                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                    .body(Body::from(msg))
-                    .unwrap(),
-                err::Error::NotLogin(msg) => Response::builder()
-                    .status(StatusCode::UNAUTHORIZED)
-                    .body(Body::from(msg))
-                    .unwrap(),
-            }
-        }
-    }
+) -> err::Result<impl IntoResponse> {
+    service::upload(
+        state.dm.divide(),
+        &state.journal,
+        state.store.clone(),
+        state.sessions.clone(),
+        &auth,
+        ds,
+    )
+    .await
+}
+
+async fn http_upload_stream(
+    auth: crypto::User,
+    hm: HeaderMap,
+    State(state): State<HttpState>,
+    Path(md5): Path<String>,
+    body: Body,
+) -> err::Result<impl IntoResponse> {
+    service::upload_stream(
+        state.dm.divide(),
+        &state.journal,
+        state.store.clone(),
+        state.sessions.clone(),
+        &auth,
+        &hm,
+        md5,
+        body,
+    )
+    .await
+}
+
+async fn http_create_session(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+    Json(sr): Json<service::SessionRequest>,
+) -> err::Result<impl IntoResponse> {
+    let info = service::create_session(
+        state.dm.divide(),
+        &state.journal,
+        state.store.clone(),
+        state.sessions.clone(),
+        &auth,
+        sr,
+    )
+    .await?;
+    Ok((StatusCode::CREATED, Json(info)))
+}
+
+async fn http_instant_upload(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+    Json(sr): Json<service::SessionRequest>,
+) -> err::Result<impl IntoResponse> {
+    let info = service::instant_upload(
+        state.dm.divide(),
+        &state.journal,
+        state.store.clone(),
+        &auth,
+        sr,
+    )
+    .await?;
+    Ok((StatusCode::CREATED, Json(info)))
+}
+
+async fn http_get_session(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+    Path(id): Path<String>,
+) -> err::Result<impl IntoResponse> {
+    let info = service::get_session(state.sessions.clone(), &auth, id).await?;
+    Ok(Json(info))
+}
+
+async fn http_upload_session(
+    auth: crypto::User,
+    hm: HeaderMap,
+    State(state): State<HttpState>,
+    Path(id): Path<String>,
+    body: Body,
+) -> err::Result<impl IntoResponse> {
+    let info = service::upload_session(
+        state.dm.divide(),
+        &state.journal,
+        state.store.clone(),
+        state.sessions.clone(),
+        &auth,
+        &hm,
+        id,
+        body,
+    )
+    .await?;
+    Ok(Json(info))
 }
 
+async fn http_delete_session(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+    Path(id): Path<String>,
+) -> err::Result<impl IntoResponse> {
+    service::delete_session(state.store.clone(), state.sessions.clone(), &auth, id).await
+}
+
+/// Serves presigned URLs without authentication, see `service::download_presigned`.
 async fn http_download(
     hm: HeaderMap,
-    State(dm): State<Arc<dyn AsDataManager>>,
+    State(state): State<HttpState>,
     Query(fr): Query<service::FileRequest>,
-) -> Response<Body> {
-    match service::download(dm.divide(), &hm, fr).await {
-        Ok(ds) => {
-            let start = ds.offset;
-            let end = ds.offset + ds.slice_value.len() as u64;
-            if start == 0 && end == ds.length {
-                Response::builder()
-                    .status(StatusCode::OK)
-                    .body(Body::from(ds.slice_value))
-                    .unwrap()
-            } else if end == ds.length {
-                Response::builder()
-                    .header("Content-Length", ds.length)
-                    .header("Content-Range", format!("{}-", start + 1))
-                    .status(StatusCode::PARTIAL_CONTENT)
-                    .body(Body::from(ds.slice_value))
-                    .unwrap()
-            } else {
-                Response::builder()
-                    .header("Content-Length", ds.length)
-                    .header("Range", format!("{}-{}", start + 1, end))
-                    .status(StatusCode::PARTIAL_CONTENT)
-                    .body(Body::from(ds.slice_value))
-                    .unwrap()
-            }
-        }
-        Err(e) => {
-            log::warn!("when http_execute:\n{e}");
-            match e {
-                err::Error::Other(msg) => Response::builder()
-                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                    .body(Body::from(msg))
-                    .unwrap(),
-                err::Error::NotLogin(msg) => Response::builder()
-                    .status(StatusCode::UNAUTHORIZED)
-                    .body(Body::from(msg))
-                    .unwrap(),
-            }
-        }
+) -> err::Result<impl IntoResponse> {
+    let ds = if fr.is_presigned() {
+        service::download_presigned(state.dm.divide(), state.store.clone(), &state.keys, &hm, fr)
+            .await?
+    } else {
+        let auth = service::authenticate(state.dm.divide(), &state.keys, &hm).await?;
+        service::download(state.dm.divide(), state.store.clone(), &auth, &hm, fr).await?
+    };
+    Ok(stream_response(ds))
+}
+
+async fn http_presign(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+    Json(pr): Json<service::PresignRequest>,
+) -> err::Result<impl IntoResponse> {
+    let url = service::presign(
+        state.dm.divide(),
+        state.store.clone(),
+        &state.keys,
+        &auth,
+        pr,
+    )
+    .await?;
+    Ok((StatusCode::CREATED, Json(url)))
+}
+
+async fn http_create_link(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+    Json(lr): Json<service::LinkRequest>,
+) -> err::Result<impl IntoResponse> {
+    let info = service::create_link(
+        state.dm.divide(),
+        &state.journal,
+        state.store.clone(),
+        &auth,
+        lr,
+    )
+    .await?;
+    Ok((StatusCode::CREATED, Json(info)))
+}
+
+async fn http_list_links(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+) -> err::Result<impl IntoResponse> {
+    let info_v = service::list_links(state.dm.divide(), &auth).await?;
+    Ok(Json(info_v))
+}
+
+async fn http_delete_link(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+    Path(slug): Path<String>,
+) -> err::Result<impl IntoResponse> {
+    service::delete_link(state.dm.divide(), &state.journal, &auth, slug).await
+}
+
+fn link_response(ds: service::DataStream, name: &str) -> Response<Body> {
+    let mime = mime_guess::from_path(name).first_or_octet_stream();
+    let mut res = stream_response(ds);
+    let header_mp = res.headers_mut();
+    if let Ok(value) = mime.essence_str().parse() {
+        header_mp.insert(header::CONTENT_TYPE, value);
+    }
+    if let Ok(value) = format!("inline; filename*=UTF-8''{}", util::escape(name)).parse() {
+        header_mp.insert(header::CONTENT_DISPOSITION, value);
     }
+    res
+}
+
+/// Serves share links without authentication, see `service::download_link`.
+async fn http_download_link(
+    method: Method,
+    hm: HeaderMap,
+    State(state): State<HttpState>,
+    Path(slug): Path<String>,
+) -> err::Result<impl IntoResponse> {
+    let la = service::LinkAccess::default();
+    let (ds, name) = service::download_link(
+        state.dm.divide(),
+        &state.journal,
+        state.store.clone(),
+        &method,
+        &hm,
+        slug,
+        la,
+    )
+    .await?;
+    Ok(link_response(ds, &name))
+}
+
+/// Serves share links with the password in the body, for clients that can not set headers.
+async fn http_download_link_with_password(
+    method: Method,
+    hm: HeaderMap,
+    State(state): State<HttpState>,
+    Path(slug): Path<String>,
+    Json(la): Json<service::LinkAccess>,
+) -> err::Result<impl IntoResponse> {
+    let (ds, name) = service::download_link(
+        state.dm.divide(),
+        &state.journal,
+        state.store.clone(),
+        &method,
+        &hm,
+        slug,
+        la,
+    )
+    .await?;
+    Ok(link_response(ds, &name))
+}
+
+async fn http_create_drop_box(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+    Json(dr): Json<service::DropBoxRequest>,
+) -> err::Result<impl IntoResponse> {
+    let info = service::create_drop_box(state.dm.divide(), &state.journal, &auth, dr).await?;
+    Ok((StatusCode::CREATED, Json(info)))
+}
+
+async fn http_list_drop_boxes(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+) -> err::Result<impl IntoResponse> {
+    let info_v = service::list_drop_boxes(state.dm.divide(), &auth).await?;
+    Ok(Json(info_v))
+}
+
+async fn http_delete_drop_box(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+    Path(slug): Path<String>,
+) -> err::Result<impl IntoResponse> {
+    service::delete_drop_box(state.dm.divide(), &state.journal, &auth, slug).await
+}
+
+/// Accepts uploads through a drop box without authentication, see `service::upload_drop_box`.
+async fn http_upload_drop_box(
+    State(state): State<HttpState>,
+    Path(slug): Path<String>,
+    Json(ds): Json<service::DataSlice>,
+) -> err::Result<impl IntoResponse> {
+    service::upload_drop_box(
+        state.dm.divide(),
+        &state.journal,
+        state.store.clone(),
+        state.sessions.clone(),
+        slug,
+        ds,
+    )
+    .await
+}
+
+async fn http_list_files(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+    Query(lr): Query<service::ListRequest>,
+) -> err::Result<impl IntoResponse> {
+    let list = service::list_files(state.dm.divide(), &auth, lr).await?;
+    Ok(Json(list))
+}
+
+/// Also serves `HEAD`, for which the metadata is in the headers.
+async fn http_stat_file(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+    Path(hash): Path<String>,
+) -> err::Result<impl IntoResponse> {
+    let info = service::stat_file(state.dm.divide(), state.sessions.clone(), &auth, hash).await?;
+    Ok((
+        [
+            (
+                header::ETAG,
+                format!("\"{}\"", info.sha256.as_deref().unwrap_or(&info.md5)),
+            ),
+            (
+                header::HeaderName::from_static("x-file-length"),
+                info.length.to_string(),
+            ),
+            (
+                header::HeaderName::from_static("x-file-type"),
+                info.mime.clone(),
+            ),
+            (
+                header::HeaderName::from_static("x-upload-state"),
+                info.state.clone(),
+            ),
+        ],
+        Json(info),
+    ))
+}
+
+async fn http_delete_file(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+    Path(hash): Path<String>,
+) -> err::Result<impl IntoResponse> {
+    service::delete_file(
+        state.dm.divide(),
+        &state.journal,
+        state.store.clone(),
+        &auth,
+        hash,
+    )
+    .await
+}
+
+async fn http_rename_file(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+    Path(hash): Path<String>,
+    Json(rr): Json<service::RenameRequest>,
+) -> err::Result<impl IntoResponse> {
+    let info = service::rename_file(state.dm.divide(), &state.journal, &auth, hash, rr).await?;
+    Ok(Json(info))
+}
+
+async fn http_share_file(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+    Path(hash): Path<String>,
+    Json(sr): Json<service::ShareRequest>,
+) -> err::Result<impl IntoResponse> {
+    let info = service::share_file(state.dm.divide(), &state.journal, &auth, hash, sr).await?;
+    Ok((StatusCode::CREATED, Json(info)))
+}
+
+async fn http_list_file_grants(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+    Path(hash): Path<String>,
+) -> err::Result<impl IntoResponse> {
+    let info_v = service::list_file_grants(state.dm.divide(), &auth, hash).await?;
+    Ok(Json(info_v))
+}
+
+async fn http_unshare_file(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+    Path((hash, grantee)): Path<(String, String)>,
+) -> err::Result<impl IntoResponse> {
+    service::unshare_file(state.dm.divide(), &state.journal, &auth, hash, grantee).await
+}
+
+async fn http_create_api_key(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+) -> err::Result<impl IntoResponse> {
+    let info = service::create_api_key(state.dm.divide(), &state.journal, &auth).await?;
+    Ok((StatusCode::CREATED, Json(info)))
+}
+
+async fn http_list_api_keys(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+) -> err::Result<impl IntoResponse> {
+    let info_v = service::list_api_keys(state.dm.divide(), &auth).await?;
+    Ok(Json(info_v))
+}
+
+async fn http_delete_api_key(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+    Path(id): Path<String>,
+) -> err::Result<impl IntoResponse> {
+    service::delete_api_key(state.dm.divide(), &state.journal, &auth, id).await
+}
+
+async fn http_list_roles(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+) -> err::Result<impl IntoResponse> {
+    let info_v = service::list_roles(state.dm.divide(), &auth).await?;
+    Ok(Json(info_v))
+}
+
+async fn http_set_role(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+    Path(email): Path<String>,
+    Json(rr): Json<service::RoleRequest>,
+) -> err::Result<impl IntoResponse> {
+    let info = service::set_role(state.dm.divide(), &state.journal, &auth, email, rr).await?;
+    Ok(Json(info))
+}
+
+async fn http_delete_role(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+    Path(email): Path<String>,
+) -> err::Result<impl IntoResponse> {
+    service::delete_role(state.dm.divide(), &state.journal, &auth, email).await
+}
+
+async fn http_revoke_user(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+    Path(email): Path<String>,
+) -> err::Result<impl IntoResponse> {
+    service::revoke_user(state.dm.divide(), &state.journal, &auth, email).await
+}
+
+async fn http_list_group_members(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+    Path(group): Path<String>,
+) -> err::Result<impl IntoResponse> {
+    let email_v = service::list_group_members(state.dm.divide(), &auth, group).await?;
+    Ok(Json(email_v))
+}
+
+async fn http_add_group_member(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+    Path((group, email)): Path<(String, String)>,
+) -> err::Result<impl IntoResponse> {
+    service::add_group_member(state.dm.divide(), &state.journal, &auth, group, email).await
+}
+
+async fn http_remove_group_member(
+    auth: crypto::User,
+    State(state): State<HttpState>,
+    Path((group, email)): Path<(String, String)>,
+) -> err::Result<impl IntoResponse> {
+    service::remove_group_member(state.dm.divide(), &state.journal, &auth, group, email).await
 }
 
 // Public
 pub struct HttpServer {
     dm: Arc<dyn AsDataManager>,
+    journal: Arc<Journal>,
+    store: Arc<dyn AsBlobStore>,
+    sessions: Arc<SessionStore>,
+    keys: Arc<KeyStore>,
 }
 
 impl HttpServer {
-    pub fn new(dm: Arc<dyn AsDataManager>) -> Self {
-        Self { dm }
+    pub fn new(
+        dm: Arc<dyn AsDataManager>,
+        journal: Arc<Journal>,
+        store: Arc<dyn AsBlobStore>,
+        sessions: Arc<SessionStore>,
+        keys: Arc<KeyStore>,
+    ) -> Self {
+        Self {
+            dm,
+            journal,
+            store,
+            sessions,
+            keys,
+        }
     }
 
     pub async fn run(self) -> io::Result<()> {
@@ -119,9 +606,112 @@ impl HttpServer {
 
         // build our application with a route
         let app = Router::new()
+            .route(&format!("/{}/register", name), routing::post(http_register))
+            .route(&format!("/{}/login", name), routing::post(http_login))
+            .route(&format!("/{}/refresh", name), routing::post(http_refresh))
+            .route(&format!("/{}/logout", name), routing::post(http_logout))
+            .route(
+                &format!("/{}/.well-known/jwks.json", name),
+                routing::get(http_jwks),
+            )
+            .route(
+                &format!("/{}/api_keys", name),
+                routing::post(http_create_api_key).get(http_list_api_keys),
+            )
+            .route(
+                &format!("/{}/api_keys/:id", name),
+                routing::delete(http_delete_api_key),
+            )
+            .route(
+                &format!("/{}/admin/roles", name),
+                routing::get(http_list_roles),
+            )
+            .route(
+                &format!("/{}/admin/roles/:email", name),
+                routing::put(http_set_role).delete(http_delete_role),
+            )
+            .route(
+                &format!("/{}/admin/users/:email/revoke", name),
+                routing::post(http_revoke_user),
+            )
+            .route(
+                &format!("/{}/admin/groups/:group/members", name),
+                routing::get(http_list_group_members),
+            )
+            .route(
+                &format!("/{}/admin/groups/:group/members/:email", name),
+                routing::put(http_add_group_member).delete(http_remove_group_member),
+            )
             .route(&format!("/{}/upload", name), routing::post(http_upload))
+            .route(
+                &format!("/{}/upload/:md5", name),
+                routing::put(http_upload_stream),
+            )
+            .route(
+                &format!("/{}/upload/sessions", name),
+                routing::post(http_create_session),
+            )
+            .route(
+                &format!("/{}/upload/instant", name),
+                routing::post(http_instant_upload),
+            )
+            .route(
+                &format!("/{}/upload/sessions/:id", name),
+                routing::get(http_get_session)
+                    .put(http_upload_session)
+                    .delete(http_delete_session),
+            )
             .route(&format!("/{}/download", name), routing::get(http_download))
-            .with_state(self.dm.clone());
+            .route(
+                &format!("/{}/download/presign", name),
+                routing::post(http_presign),
+            )
+            .route(
+                &format!("/{}/links", name),
+                routing::post(http_create_link).get(http_list_links),
+            )
+            .route(
+                &format!("/{}/links/:slug", name),
+                routing::delete(http_delete_link),
+            )
+            .route(
+                &format!("/{}/s/:slug", name),
+                routing::get(http_download_link).post(http_download_link_with_password),
+            )
+            .route(
+                &format!("/{}/drop_boxes", name),
+                routing::post(http_create_drop_box).get(http_list_drop_boxes),
+            )
+            .route(
+                &format!("/{}/drop_boxes/:slug", name),
+                routing::delete(http_delete_drop_box),
+            )
+            .route(
+                &format!("/{}/d/:slug/upload", name),
+                routing::post(http_upload_drop_box),
+            )
+            .route(&format!("/{}/files", name), routing::get(http_list_files))
+            .route(
+                &format!("/{}/files/:hash", name),
+                routing::get(http_stat_file)
+                    .patch(http_rename_file)
+                    .delete(http_delete_file),
+            )
+            .route(
+                &format!("/{}/files/:hash/acl", name),
+                routing::get(http_list_file_grants).post(http_share_file),
+            )
+            .route(
+                &format!("/{}/files/:hash/acl/:grantee", name),
+                routing::delete(http_unshare_file),
+            )
+            .with_state(HttpState {
+                dm: self.dm.clone(),
+                journal: self.journal.clone(),
+                store: self.store.clone(),
+                sessions: self.sessions.clone(),
+                keys: self.keys.clone(),
+            });
         // run our app with hyper, listening globally on port 3000
         let address = format!("{}:{}", ip, port);
         log::info!("serving at {address}/{}", name);
@@ -1,38 +1,75 @@
-use std::{collections::BTreeMap, io, time};
+use std::io;
 
-use hmac::{digest::KeyInit, Hmac};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use ed25519_dalek::{Signature, Signer, Verifier};
+use hmac::{Hmac, Mac};
 use jwt::{AlgorithmType, Header, SignWithKey, Token, VerifyWithKey};
 use serde::{Deserialize, Serialize};
-use sha2::Sha512;
+use sha2::{Sha256, Sha512};
 
-use crate::{err, util};
+use crate::{
+    err,
+    jwks::PublicKey,
+    keyring::{KeyAlg, SigningKey},
+    revocation::RevocationList,
+    role::{self, Role, Scope},
+    util,
+};
 
-#[derive(Debug, Serialize)]
-pub struct User {
-    pub email: String,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    iss: String,
+    aud: String,
+    exp: u64,
+    iat: u64,
+    nbf: u64,
+    /// Session of the token, absent in tokens of other issuers.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    jti: String,
+    /// Space-separated scopes, absent in tokens of other issuers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    scope: Option<String>,
 }
 
+/// Claims naming whose a token is, read before it is verified.
 #[derive(Debug, Deserialize)]
-pub struct Auth {
-    pub email: String,
-    pub password: String,
+struct Subject {
+    #[serde(default)]
+    sub: String,
+    #[serde(default)]
+    jti: String,
 }
 
-pub fn gen_token(key: &str, auth: &Auth) -> io::Result<String> {
-    let key: Hmac<Sha512> =
-        Hmac::new_from_slice(&util::hex2byte_v(key)).map_err(|e| io::Error::other(e))?;
+/// Header of a token of any algorithm. The jwt crate knows no EdDSA.
+#[derive(Debug, Serialize, Deserialize)]
+struct JoseHeader {
+    alg: String,
+    #[serde(default)]
+    typ: Option<String>,
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+fn decode_part<T: serde::de::DeserializeOwned>(part: &str) -> err::Result<T> {
+    let byte_v = URL_SAFE_NO_PAD
+        .decode(part.as_bytes())
+        .map_err(|e| err::Error::NotLogin(e.to_string()))?;
+    serde_json::from_slice(&byte_v).map_err(|e| err::Error::NotLogin(e.to_string()))
+}
+
+fn encode_part<T: Serialize>(part: &T) -> io::Result<String> {
+    Ok(URL_SAFE_NO_PAD.encode(serde_json::to_vec(part).map_err(io::Error::other)?))
+}
+
+fn sign_hs512(key: &SigningKey, claims: Claims) -> io::Result<String> {
     let header = Header {
         algorithm: AlgorithmType::Hs512,
+        key_id: Some(key.kid.clone()),
         ..Default::default()
     };
-    let mut claims = BTreeMap::new();
-    let exp = time::SystemTime::now()
-        .duration_since(time::UNIX_EPOCH)
-        .expect("can not get timestamp")
-        .as_secs()
-        + 3600;
-    claims.insert("email", auth.email.clone());
-    claims.insert("exp", format!("{exp}"));
+    let key: Hmac<Sha512> =
+        Hmac::new_from_slice(&util::hex2byte_v(&key.secret)).map_err(|e| io::Error::other(e))?;
     Ok(Token::new(header, claims)
         .sign_with_key(&key)
         .map_err(|e| io::Error::other(e))?
@@ -40,38 +77,348 @@ pub fn gen_token(key: &str, auth: &Auth) -> io::Result<String> {
         .to_string())
 }
 
-pub fn parse_token(key: &str, token_str: &str) -> err::Result<User> {
-    let key: Hmac<Sha512> =
-        Hmac::new_from_slice(&util::hex2byte_v(key)).map_err(|e| err::Error::NotLogin(e.to_string()))?;
-    let token: Token<Header, BTreeMap<String, String>, _> = token_str
+fn sign_eddsa(key: &SigningKey, claims: Claims) -> io::Result<String> {
+    let seed: [u8; 32] = util::hex2byte_v(&key.secret)
+        .try_into()
+        .map_err(|_| io::Error::other(format!("invalid seed of key {}", key.kid)))?;
+    let header = JoseHeader {
+        alg: format!("EdDSA"),
+        typ: Some(format!("JWT")),
+        kid: Some(key.kid.clone()),
+    };
+    let message = format!("{}.{}", encode_part(&header)?, encode_part(&claims)?);
+    let signature = ed25519_dalek::SigningKey::from_bytes(&seed).sign(message.as_bytes());
+    Ok(format!(
+        "{message}.{}",
+        URL_SAFE_NO_PAD.encode(signature.to_bytes())
+    ))
+}
+
+fn verify_hs512(key_v: &[SigningKey], kid: &str, token_str: &str) -> err::Result<Claims> {
+    let key = key_v
+        .iter()
+        .find(|key| key.alg == KeyAlg::Hs512 && key.kid == kid && !key.secret.is_empty())
+        .ok_or(err::Error::NotLogin(format!("unknown kid: {kid}")))?;
+    let key: Hmac<Sha512> = Hmac::new_from_slice(&util::hex2byte_v(&key.secret))
+        .map_err(|e| err::Error::NotLogin(e.to_string()))?;
+    let token: Token<Header, Claims, _> = token_str
         .verify_with_key(&key)
         .map_err(|e| err::Error::NotLogin(e.to_string()))?;
-    let claims = token.claims();
-    let exp = claims
-        .get("exp")
-        .ok_or(err::Error::NotLogin("no exp".to_string()))?
-        .parse::<u64>()
+    Ok(token.claims().clone())
+}
+
+/// Verify an EdDSA token, returning its claims and whether its key is of another issuer.
+fn verify_eddsa(public_v: &[PublicKey], kid: &str, token_str: &str) -> err::Result<(Claims, bool)> {
+    let key = public_v
+        .iter()
+        .find(|key| key.kid == kid)
+        .ok_or(err::Error::NotLogin(format!("unknown kid: {kid}")))?;
+    let (message, signature) = token_str
+        .rsplit_once('.')
+        .ok_or(err::Error::NotLogin(format!("malformed token")))?;
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature.as_bytes())
+        .map_err(|e| err::Error::NotLogin(e.to_string()))?;
+    let signature =
+        Signature::from_slice(&signature).map_err(|e| err::Error::NotLogin(e.to_string()))?;
+    key.key
+        .verify(message.as_bytes(), &signature)
         .map_err(|e| err::Error::NotLogin(e.to_string()))?;
-    let now = time::SystemTime::now()
-        .duration_since(time::UNIX_EPOCH)
-        .expect("can not get timestamp")
-        .as_secs();
-    if exp - now > 3600 {
-        return Err(err::Error::NotLogin(format!("invalid token")));
-    }
-    let email = claims
-        .get("email")
-        .ok_or(err::Error::NotLogin("no email".to_string()))?;
+    let (_, claims) = message
+        .split_once('.')
+        .ok_or(err::Error::NotLogin(format!("malformed token")))?;
+    Ok((decode_part(claims)?, key.is_foreign))
+}
+
+fn validate(option: &TokenOption, claims: &Claims, now: u64) -> err::Result<()> {
+    if now > claims.exp.saturating_add(option.leeway) {
+        return Err(err::Error::TokenExpired(claims.exp));
+    }
+    if claims.nbf > now.saturating_add(option.leeway) {
+        return Err(err::Error::NotLogin(format!(
+            "token is not valid before {}",
+            claims.nbf
+        )));
+    }
+    if claims.iat > now.saturating_add(option.leeway) {
+        return Err(err::Error::NotLogin(format!(
+            "token is issued in the future at {}",
+            claims.iat
+        )));
+    }
+    if claims.iss != option.issuer {
+        return Err(err::Error::NotLogin(format!(
+            "invalid issuer: {}",
+            claims.iss
+        )));
+    }
+    if claims.aud != option.audience {
+        return Err(err::Error::NotLogin(format!(
+            "invalid audience: {}",
+            claims.aud
+        )));
+    }
+    Ok(())
+}
+
+fn gen_token_at(
+    option: &TokenOption,
+    key: &SigningKey,
+    email: &str,
+    session: &str,
+    scope_v: &[Scope],
+    now: u64,
+) -> io::Result<String> {
+    let claims = Claims {
+        sub: email.to_string(),
+        iss: option.issuer.clone(),
+        aud: option.audience.clone(),
+        exp: now + option.ttl,
+        iat: now,
+        nbf: now,
+        jti: session.to_string(),
+        scope: Some(role::format_scope(scope_v)),
+    };
+    match key.alg {
+        KeyAlg::Hs512 => sign_hs512(key, claims),
+        KeyAlg::EdDsa => sign_eddsa(key, claims),
+    }
+}
+
+fn parse_token_at(
+    option: &TokenOption,
+    key_v: &[SigningKey],
+    public_v: &[PublicKey],
+    revoked: &RevocationList,
+    role_scope_v: &[Scope],
+    token_str: &str,
+    now: u64,
+) -> err::Result<User> {
+    let (header, _) = token_str
+        .split_once('.')
+        .ok_or(err::Error::NotLogin(format!("malformed token")))?;
+    let header: JoseHeader = decode_part(header)?;
+    let kid = header
+        .kid
+        .as_ref()
+        .ok_or(err::Error::NotLogin(format!("no kid")))?;
+    // The algorithm a key is used with comes from the key, never from the token alone.
+    let (claims, is_foreign) = match header.alg.as_str() {
+        "HS512" => (verify_hs512(key_v, kid, token_str)?, false),
+        "EdDSA" => verify_eddsa(public_v, kid, token_str)?,
+        alg => {
+            return Err(err::Error::NotLogin(format!(
+                "unsupported algorithm: {alg}"
+            )))
+        }
+    };
+    validate(option, &claims, now)?;
+    if revoked.is_revoked(&claims.jti, &claims.sub, claims.iat) {
+        return Err(err::Error::NotLogin(format!("token is revoked")));
+    }
+    let mut scope_v = match &claims.scope {
+        Some(scope) => role::parse_scope(scope),
+        None => Role::default().scope_v(),
+    };
+    if is_foreign {
+        // Another issuer may narrow the role of the user here, but never widen it.
+        scope_v.retain(|scope| *scope != Scope::Admin && role_scope_v.contains(scope));
+    }
     Ok(User {
-        email: email.clone(),
+        email: claims.sub,
+        session: if claims.jti.is_empty() {
+            None
+        } else {
+            Some(claims.jti)
+        },
+        scope_v,
     })
 }
 
+/// Derive the key of presigned URLs from `secret` by HKDF-SHA256 with info `presign`, so that
+/// URLs and tokens never share a key.
+fn url_key(secret: &str) -> io::Result<Hmac<Sha256>> {
+    // Extract with the default salt, then expand a single block.
+    let mut mac: Hmac<Sha256> = Hmac::new_from_slice(&[0; 32]).map_err(io::Error::other)?;
+    mac.update(&util::hex2byte_v(secret));
+    let prk = mac.finalize().into_bytes();
+    let mut mac: Hmac<Sha256> = Hmac::new_from_slice(&prk).map_err(io::Error::other)?;
+    mac.update(b"presign");
+    mac.update(&[1]);
+    Hmac::new_from_slice(&mac.finalize().into_bytes()).map_err(io::Error::other)
+}
+
+// Public
+#[derive(Debug, Serialize)]
+pub struct User {
+    pub email: String,
+    /// Login session of the token, `None` for API keys and tokens of other issuers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    pub scope_v: Vec<Scope>,
+}
+
+impl User {
+    pub fn has(&self, scope: Scope) -> bool {
+        self.scope_v.contains(&scope)
+    }
+
+    /// Fail with `Forbidden` unless the user has `scope`.
+    pub fn require(&self, scope: Scope) -> err::Result<()> {
+        if self.has(scope) {
+            Ok(())
+        } else {
+            Err(err::Error::Forbidden(format!(
+                "{} lacks scope {scope}",
+                self.email
+            )))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Auth {
+    pub email: String,
+    pub password: String,
+}
+
+/// How tokens are issued and validated.
+#[derive(Debug, Clone)]
+pub struct TokenOption {
+    /// `iss` of issued tokens, and the only `iss` accepted.
+    pub issuer: String,
+    /// `aud` of issued tokens, and the only `aud` accepted.
+    pub audience: String,
+    /// Lifetime in seconds.
+    pub ttl: u64,
+    /// Tolerated clock skew in seconds.
+    pub leeway: u64,
+}
+
+/// Sign a token of login session `session` for `email`, granting `scope_v`, with `key`.
+pub fn gen_token(
+    option: &TokenOption,
+    key: &SigningKey,
+    email: &str,
+    session: &str,
+    scope_v: &[Scope],
+) -> io::Result<String> {
+    gen_token_at(option, key, email, session, scope_v, util::timestamp())
+}
+
+/// Verify a token against the key named by its `kid`: an HS512 key in `key_v`, or an Ed25519
+/// public key in `public_v`, rejecting it if `revoked` lists its session or user.
+///
+/// Tokens of other issuers get no scope beyond `role_scope_v`, the scopes of the role of the user
+/// here, and never `admin`.
+pub fn parse_token(
+    option: &TokenOption,
+    key_v: &[SigningKey],
+    public_v: &[PublicKey],
+    revoked: &RevocationList,
+    role_scope_v: &[Scope],
+    token_str: &str,
+) -> err::Result<User> {
+    parse_token_at(
+        option,
+        key_v,
+        public_v,
+        revoked,
+        role_scope_v,
+        token_str,
+        util::timestamp(),
+    )
+}
+
+/// Read the session and subject of a token without verifying it, so that only their
+/// revocations are loaded for `parse_token`.
+pub fn peek_subject(token_str: &str) -> err::Result<(String, String)> {
+    let payload = token_str
+        .split('.')
+        .nth(1)
+        .ok_or(err::Error::NotLogin(format!("malformed token")))?;
+    let subject: Subject = decode_part(payload)?;
+    Ok((subject.jti, subject.sub))
+}
+
+/// Read the algorithm of a token without verifying it.
+pub fn peek_alg(token_str: &str) -> err::Result<String> {
+    let (header, _) = token_str
+        .split_once('.')
+        .ok_or(err::Error::NotLogin(format!("malformed token")))?;
+    let header: JoseHeader = decode_part(header)?;
+    Ok(header.alg)
+}
+
+/// Sign `message` of a presigned URL with `key`, as hex HMAC-SHA256.
+///
+/// Any key with a secret signs URLs, with a key derived from the secret, or the seed of an
+/// Ed25519 key.
+pub fn sign_url(key: &SigningKey, message: &str) -> io::Result<String> {
+    if key.secret.is_empty() {
+        return Err(io::Error::other(format!("key {} has no secret", key.kid)));
+    }
+    let mut mac = url_key(&key.secret)?;
+    mac.update(message.as_bytes());
+    Ok(util::byte_v2hex(&mac.finalize().into_bytes()))
+}
+
+/// Verify `signature` of `message` of a presigned URL against the key in `key_v` named `kid`.
+pub fn verify_url(
+    key_v: &[SigningKey],
+    kid: &str,
+    message: &str,
+    signature: &str,
+) -> err::Result<()> {
+    if signature.len() != 64 || !signature.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(err::Error::NotLogin(format!("invalid signature")));
+    }
+    let key = key_v
+        .iter()
+        .find(|key| key.kid == kid && !key.secret.is_empty())
+        .ok_or(err::Error::NotLogin(format!("unknown kid: {kid}")))?;
+    let mut mac = url_key(&key.secret).map_err(|e| err::Error::NotLogin(e.to_string()))?;
+    mac.update(message.as_bytes());
+    mac.verify_slice(&util::hex2byte_v(signature))
+        .map_err(|_| err::Error::NotLogin(format!("invalid signature")))
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::util::{byte_v2hex, hex2byte_v};
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
 
-    use super::{gen_token, parse_token};
+    use crate::{
+        err,
+        keyring::{KeyAlg, KeyState, SigningKey},
+        revocation::{Revocation, RevocationList},
+        role::{self, Role, Scope},
+        util::{byte_v2hex, hex2byte_v},
+    };
+
+    use super::{
+        gen_token, gen_token_at, parse_token, parse_token_at, peek_subject, sign_url, verify_url,
+        TokenOption,
+    };
+
+    fn option() -> TokenOption {
+        TokenOption {
+            issuer: "pool".to_string(),
+            audience: "pool".to_string(),
+            ttl: 3600,
+            leeway: 60,
+        }
+    }
+
+    fn key(kid: &str, secret: &str) -> SigningKey {
+        SigningKey {
+            kid: kid.to_string(),
+            alg: KeyAlg::Hs512,
+            secret: secret.to_string(),
+            state: KeyState::Current,
+            created_at: 0,
+        }
+    }
 
     #[test]
     fn test_hex() {
@@ -83,16 +430,187 @@ mod tests {
 
     #[test]
     fn test() {
-        let key = "a";
-        let token = gen_token(
-            key,
-            &super::Auth {
-                email: format!("email"),
-                password: format!("password"),
-            },
+        let option = option();
+        let revoked = RevocationList::default();
+        let key_v = vec![key("0", "a"), key("1", "b")];
+        let token = gen_token(&option, &key_v[1], "email", "s0", &[Scope::Download]).unwrap();
+        let user = parse_token(&option, &key_v, &[], &revoked, &[], &token).unwrap();
+        assert_eq!(user.email, "email");
+
+        // A token is rejected once its key is gone, and can not name another key.
+        assert!(parse_token(&option, &key_v[0..1], &[], &revoked, &[], &token).is_err());
+        let forged = vec![key("1", "a")];
+        assert!(parse_token(&option, &forged, &[], &revoked, &[], &token).is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let option = option();
+        let revoked = RevocationList::default();
+        let key_v = vec![key("0", "a")];
+        let token =
+            gen_token_at(&option, &key_v[0], "email", "s0", &[Scope::Download], 1000).unwrap();
+        assert!(parse_token_at(
+            &option,
+            &key_v,
+            &[],
+            &revoked,
+            &[],
+            &token,
+            1000 + 3600 + 60
         )
-        .unwrap();
-        let user = parse_token(key, &token).unwrap();
+        .is_ok());
+        assert!(matches!(
+            parse_token_at(
+                &option,
+                &key_v,
+                &[],
+                &revoked,
+                &[],
+                &token,
+                1000 + 3600 + 61
+            ),
+            Err(err::Error::TokenExpired(4600))
+        ));
+        assert!(parse_token_at(&option, &key_v, &[], &revoked, &[], &token, 1000 - 60).is_ok());
+        assert!(matches!(
+            parse_token_at(&option, &key_v, &[], &revoked, &[], &token, 1000 - 61),
+            Err(err::Error::NotLogin(_))
+        ));
+
+        let other = TokenOption {
+            audience: "other".to_string(),
+            ..option.clone()
+        };
+        assert!(matches!(
+            parse_token_at(&other, &key_v, &[], &revoked, &[], &token, 1000),
+            Err(err::Error::NotLogin(_))
+        ));
+        assert!(matches!(
+            parse_token_at(&option, &[key("0", "b")], &[], &revoked, &[], &token, 1000),
+            Err(err::Error::NotLogin(_))
+        ));
+    }
+
+    #[test]
+    fn test_eddsa() {
+        let option = option();
+        let revoked = RevocationList::default();
+        let mut signing = key("e", &byte_v2hex(&[7; 32]));
+        signing.alg = KeyAlg::EdDsa;
+        let public_v = vec![signing.public_key().unwrap()];
+        let token = gen_token(&option, &signing, "email", "s0", &[Scope::Download]).unwrap();
+
+        // A pool holding only the public key accepts the token.
+        let user = parse_token(&option, &[], &public_v, &revoked, &[], &token).unwrap();
         assert_eq!(user.email, "email");
+        assert!(parse_token(&option, &[], &[], &revoked, &[], &token).is_err());
+
+        // Neither an HMAC key of the same kid nor a tampered payload passes.
+        assert!(parse_token(&option, &[key("e", "a")], &[], &revoked, &[], &token).is_err());
+        let part_v: Vec<&str> = token.split('.').collect();
+        let claims = super::encode_part(&serde_json::json!({
+            "sub": "admin", "iss": "pool", "aud": "pool", "exp": u64::MAX, "iat": 0, "nbf": 0
+        }))
+        .unwrap();
+        let tampered = format!("{}.{claims}.{}", part_v[0], part_v[2]);
+        assert!(parse_token(&option, &[], &public_v, &revoked, &[], &tampered).is_err());
+
+        // An HS512 token can not be checked against an empty secret.
+        let token = gen_token(&option, &key("0", ""), "email", "s0", &[Scope::Download]).unwrap();
+        assert!(parse_token(&option, &[key("0", "")], &public_v, &revoked, &[], &token).is_err());
+    }
+
+    #[test]
+    fn test_revoked() {
+        let option = option();
+        let key_v = vec![key("0", "a")];
+        let token =
+            gen_token_at(&option, &key_v[0], "email", "s0", &[Scope::Download], 1000).unwrap();
+        assert_eq!(
+            peek_subject(&token).unwrap(),
+            ("s0".to_string(), "email".to_string())
+        );
+        assert!(peek_subject("garbage").is_err());
+        let revoked = RevocationList::default();
+        let user = parse_token_at(&option, &key_v, &[], &revoked, &[], &token, 1000).unwrap();
+        assert_eq!(user.session, Some("s0".to_string()));
+        assert!(user.has(Scope::Download));
+        assert!(matches!(
+            user.require(Scope::Upload),
+            Err(err::Error::Forbidden(_))
+        ));
+
+        let mut revoked = RevocationList::default();
+        revoked.add(Revocation {
+            jti: "s0".to_string(),
+            ..Default::default()
+        });
+        assert!(parse_token_at(&option, &key_v, &[], &revoked, &[], &token, 1000).is_err());
+
+        let mut revoked = RevocationList::default();
+        revoked.add(Revocation {
+            sub: "email".to_string(),
+            revoked_at: 1000,
+            ..Default::default()
+        });
+        assert!(parse_token_at(&option, &key_v, &[], &revoked, &[], &token, 1000).is_err());
+        let token =
+            gen_token_at(&option, &key_v[0], "email", "s1", &[Scope::Download], 1001).unwrap();
+        assert!(parse_token_at(&option, &key_v, &[], &revoked, &[], &token, 1001).is_ok());
+    }
+
+    #[test]
+    fn test_scope() {
+        let option = option();
+        let revoked = RevocationList::default();
+        let mut signing = key("e", &byte_v2hex(&[7; 32]));
+        signing.alg = KeyAlg::EdDsa;
+        let mut public_v = vec![signing.public_key().unwrap()];
+        public_v[0].is_foreign = true;
+        let role_scope_v = Role::default().scope_v();
+
+        // Tokens of other issuers without a scope get the scopes of the default role.
+        let mut claims = super::Claims {
+            sub: "email".to_string(),
+            iss: "pool".to_string(),
+            aud: "pool".to_string(),
+            exp: u64::MAX,
+            iat: 0,
+            nbf: 0,
+            jti: String::new(),
+            scope: None,
+        };
+        let token = super::sign_eddsa(&signing, claims.clone()).unwrap();
+        let user = parse_token(&option, &[], &public_v, &revoked, &role_scope_v, &token).unwrap();
+        assert_eq!(user.scope_v, Role::default().scope_v());
+        assert_eq!(user.session, None);
+
+        // They never get admin nor more than the role of the user here.
+        claims.scope = Some(role::format_scope(&Role::Admin.scope_v()));
+        let token = super::sign_eddsa(&signing, claims.clone()).unwrap();
+        let user = parse_token(&option, &[], &public_v, &revoked, &role_scope_v, &token).unwrap();
+        assert_eq!(user.scope_v, role_scope_v);
+        let reader_scope_v = Role::Reader.scope_v();
+        let user = parse_token(&option, &[], &public_v, &revoked, &reader_scope_v, &token).unwrap();
+        assert_eq!(user.scope_v, vec![Scope::Download]);
+        let user = parse_token(&option, &[], &public_v, &revoked, &[], &token).unwrap();
+        assert!(user.scope_v.is_empty());
+    }
+
+    #[test]
+    fn test_url() {
+        let key_v = vec![key("0", "ab"), key("1", "")];
+        let signature = sign_url(&key_v[0], "md5=00\n1000\n\n").unwrap();
+        assert!(verify_url(&key_v, "0", "md5=00\n1000\n\n", &signature).is_ok());
+        assert!(verify_url(&key_v, "0", "md5=00\n1001\n\n", &signature).is_err());
+        assert!(verify_url(&key_v, "1", "md5=00\n1000\n\n", &signature).is_err());
+        assert!(verify_url(&key_v, "0", "md5=00\n1000\n\n", "!").is_err());
+        assert!(sign_url(&key_v[1], "").is_err());
+
+        // The secret itself does not sign URLs.
+        let mut mac: Hmac<Sha256> = Hmac::new_from_slice(&hex2byte_v("ab")).unwrap();
+        mac.update(b"md5=00\n1000\n\n");
+        assert_ne!(signature, byte_v2hex(&mac.finalize().into_bytes()));
     }
 }
@@ -1,64 +1,778 @@
 use std::{
-    collections::HashMap,
-    fs,
-    io::{Read, Seek, Write},
+    collections::{BTreeMap, HashMap},
     sync::Arc,
 };
 
-use axum::http::HeaderMap;
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, Method},
+};
 use edge_lib::{data::AsDataManager, Path};
-use serde::Deserialize;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
 
-use crate::err;
+use crate::{
+    acl::{self, Right},
+    api_key::{self, ApiKeyRecord},
+    drop_box::{self, DropBoxRecord},
+    err,
+    file::{self, FileRecord},
+    graph::Journal,
+    jwks,
+    keyring::KeyStore,
+    refresh_token::{self, RefreshTokenRecord},
+    revocation::{self, RevocationList},
+    role::{self, Role, Scope},
+    session::{SessionStore, UploadSession},
+    share_link::{self, ShareLinkRecord},
+    storage::{AsBlobStore, BlobReader, Digest},
+    user::{self, UserRecord},
+    util,
+};
 
 use super::crypto;
 
-// Public
-pub fn get_cookie(hm: &HeaderMap) -> err::Result<HashMap<String, String>> {
-    let cookie: &str = match hm.get("Cookie") {
-        Some(r) => match r.to_str() {
-            Ok(r) => r,
-            Err(e) => {
-                return Err(err::Error::Other(e.to_string()));
+const STREAM_BUFFER_SIZE: usize = 1024 * 1024;
+
+const DEFAULT_PAGE_SIZE: usize = 100;
+
+const MAX_PAGE_SIZE: usize = 1000;
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+const API_KEY_HEADER: &str = "x-api-key";
+
+const LINK_PASSWORD_HEADER: &str = "x-link-password";
+
+/// Wrong passwords of a share link allowed per `LINK_FAILURE_WINDOW`.
+const MAX_LINK_FAILURES: u64 = 5;
+
+const LINK_FAILURE_WINDOW: u64 = 60;
+
+const DEFAULT_PRESIGN_TTL: u64 = 15 * 60;
+
+const MAX_PRESIGN_TTL: u64 = 7 * 24 * 3600;
+
+static REGISTER_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+static REFRESH_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+static SHARE_LINK_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+static DROP_BOX_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Start of the current window and wrong passwords in it, by share link slug.
+static LINK_FAILURE_MP: std::sync::Mutex<BTreeMap<String, (u64, u64)>> =
+    std::sync::Mutex::new(BTreeMap::new());
+
+/// Verify temp blob `id` against `md5`, then finalize it as the blob keyed by its sha256.
+///
+/// A mismatching temp blob is dropped.
+async fn finalize(store: &dyn AsBlobStore, id: &str, md5: &str) -> err::Result<Digest> {
+    let digest = store
+        .digest(id)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    let actual = &digest.md5;
+    if actual != md5 {
+        if let Err(e) = store.abort(id).await {
+            log::warn!("{e}\nwhen abort {id}");
+        }
+        return Err(err::Error::HashMismatch(format!(
+            "expected {md5}, but got {actual}"
+        )));
+    }
+    store
+        .finalize(id, &digest.sha256)
+        .await
+        .map_err(err::Error::from)?;
+    Ok(digest)
+}
+
+/// Find the key of the blob with the given digests.
+///
+/// Blobs stored before sha256 addressing are found by their md5 alone.
+async fn find_key(
+    dm: Arc<dyn AsDataManager>,
+    store: &dyn AsBlobStore,
+    md5: Option<&str>,
+    sha256: Option<&str>,
+) -> err::Result<Option<String>> {
+    if let (None, Some(sha256)) = (md5, sha256) {
+        let length = store
+            .stat(sha256)
+            .await
+            .map_err(|e| err::Error::Other(e.to_string()))?;
+        return Ok(length.map(|_| sha256.to_string()));
+    }
+    let record_v = file::find(dm, md5.or(sha256).unwrap_or_default())
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    if let Some((_, record)) = record_v.into_iter().find(|(_, record)| {
+        !record.sha256.is_empty()
+            && md5.is_none_or(|md5| md5 == record.md5)
+            && sha256.is_none_or(|sha256| sha256 == record.sha256)
+    }) {
+        return Ok(Some(record.sha256));
+    }
+    if let (Some(md5), None) = (md5, sha256) {
+        let length = store
+            .stat(md5)
+            .await
+            .map_err(|e| err::Error::Other(e.to_string()))?;
+        return Ok(length.map(|_| md5.to_string()));
+    }
+    Ok(None)
+}
+
+/// Lowercase and validate optional digests, at least one of which is required.
+fn parse_digests(
+    md5: Option<String>,
+    sha256: Option<String>,
+) -> err::Result<(Option<String>, Option<String>)> {
+    let md5 = md5.map(|md5| md5.to_lowercase());
+    let sha256 = sha256.map(|sha256| sha256.to_lowercase());
+    if let Some(md5) = &md5 {
+        if !util::is_md5(md5) {
+            return Err(err::Error::BadRequest(format!("invalid md5: {md5}")));
+        }
+    }
+    if let Some(sha256) = &sha256 {
+        if !util::is_sha256(sha256) {
+            return Err(err::Error::BadRequest(format!("invalid sha256: {sha256}")));
+        }
+    }
+    if md5.is_none() && sha256.is_none() {
+        return Err(err::Error::BadRequest(format!("no md5 or sha256")));
+    }
+    Ok((md5, sha256))
+}
+
+/// Id of the session of a legacy upload of `md5` by `owner`, so that users uploading the same
+/// content never share a session or its temp blob.
+fn md5_session_id(owner: &str, md5: &str) -> String {
+    format!("{:x}", Sha256::digest(format!("{owner}\n{md5}").as_bytes()))
+}
+
+/// Get the session of a legacy upload of `md5` by `owner`, creating it and its temp blob if
+/// needed.
+async fn open_md5_session(
+    store: &dyn AsBlobStore,
+    sessions: &SessionStore,
+    md5: &str,
+    length: u64,
+    owner: &str,
+) -> err::Result<UploadSession> {
+    let id = md5_session_id(owner, md5);
+    open_session(store, sessions, &id, md5, length, "", owner).await
+}
+
+/// Whether the legacy upload of `md5` by `owner` has already been finalized, so that a chunk
+/// sent again afterwards does not open a new session.
+async fn is_md5_uploaded(
+    dm: Arc<dyn AsDataManager>,
+    sessions: &SessionStore,
+    md5: &str,
+    length: u64,
+    owner: &str,
+) -> err::Result<bool> {
+    if sessions
+        .get(&md5_session_id(owner, md5))
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+        .is_some()
+    {
+        return Ok(false);
+    }
+    Ok(file::find(dm, md5)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+        .iter()
+        .any(|(_, record)| record.owner == owner && record.md5 == md5 && record.length == length))
+}
+
+/// Get session `id`, creating it and its temp blob if needed.
+async fn open_session(
+    store: &dyn AsBlobStore,
+    sessions: &SessionStore,
+    id: &str,
+    md5: &str,
+    length: u64,
+    name: &str,
+    owner: &str,
+) -> err::Result<UploadSession> {
+    let (session, is_created) = sessions
+        .get_or_create(id, md5, length, name, owner)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    if session.length != length {
+        return Err(err::Error::Conflict(format!(
+            "length {length} does not match {}",
+            session.length
+        )));
+    }
+    if is_created {
+        store.begin(id, length).await.map_err(err::Error::from)?;
+    }
+    Ok(session)
+}
+
+/// Find drop box `slug` for a new file, failing if the box is gone or full.
+///
+/// Files still uploading through the box hold room for themselves until they complete or their
+/// session expires.
+async fn find_drop_box_room(
+    dm: Arc<dyn AsDataManager>,
+    sessions: &SessionStore,
+    slug: &str,
+) -> err::Result<(String, DropBoxRecord)> {
+    let not_found = || err::Error::NotFound(format!("no drop box {slug}"));
+    let (node, record) = drop_box::find(dm, slug)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+        .ok_or_else(not_found)?;
+    let now = util::timestamp();
+    if record.is_expired(now) {
+        return Err(not_found());
+    }
+    let uploading = sessions
+        .list_by_drop_box(slug, now)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+        .len();
+    if record.is_full(uploading as u64) {
+        return Err(err::Error::Forbidden(format!("drop box {slug} is full")));
+    }
+    Ok((node, record))
+}
+
+/// Mark `start..end` of session `id` as committed after its bytes have been written.
+///
+/// The request that completes the coverage finalizes the session, so that it is finalized
+/// exactly once, and records the file in the graph.
+async fn commit_range(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    store: &dyn AsBlobStore,
+    sessions: &SessionStore,
+    id: &str,
+    start: u64,
+    end: u64,
+) -> err::Result<UploadSession> {
+    let mut is_completed_here = false;
+    let session = sessions
+        .update(id, |session| {
+            session.commit(start, end);
+            if session.is_complete() && !session.is_finalizing {
+                session.is_finalizing = true;
+                is_completed_here = true;
             }
-        },
-        None => {
-            return Err(err::Error::Other(format!("no cookie")));
+        })
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+        .ok_or(err::Error::NotFound(format!("no session {id}")))?;
+    if is_completed_here {
+        // The file takes over the room its session held in a drop box, at once for openers.
+        let _guard = if session.drop_box.is_empty() {
+            None
+        } else {
+            Some(DROP_BOX_LOCK.lock().await)
+        };
+        let r = finalize(store, &session.id, &session.md5).await;
+        sessions
+            .remove(&session.id)
+            .await
+            .map_err(|e| err::Error::Other(e.to_string()))?;
+        let digest = r?;
+        FileRecord::new(
+            &session.md5,
+            &digest.sha256,
+            session.length,
+            &session.name,
+            &session.owner,
+        )
+        .insert(dm.clone(), journal)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+        if !session.drop_box.is_empty() {
+            let drop_box = drop_box::find(dm.clone(), &session.drop_box)
+                .await
+                .map_err(|e| err::Error::Other(e.to_string()))?;
+            if let Some((node, record)) = drop_box {
+                drop_box::set_file_count(dm, journal, &node, record.file_count + 1)
+                    .await
+                    .map_err(|e| err::Error::Other(e.to_string()))?;
+            }
+        }
+    }
+    Ok(session)
+}
+
+/// Parse `Content-Range: bytes a-b/total` into `(a, b + 1, total)`.
+fn parse_content_range(s: &str) -> err::Result<(u64, u64, u64)> {
+    let invalid = || err::Error::BadRequest(format!("invalid Content-Range: {s}"));
+    let (range, total) = s
+        .trim()
+        .strip_prefix("bytes ")
+        .and_then(|s| s.split_once('/'))
+        .ok_or_else(invalid)?;
+    let (start, last) = range.split_once('-').ok_or_else(invalid)?;
+    let start: u64 = start.trim().parse().map_err(|_| invalid())?;
+    let last: u64 = last.trim().parse().map_err(|_| invalid())?;
+    let total: u64 = total.trim().parse().map_err(|_| invalid())?;
+    if start > last || last >= total {
+        return Err(invalid());
+    }
+    Ok((start, last + 1, total))
+}
+
+fn get_content_range(hm: &HeaderMap) -> err::Result<(u64, u64, u64)> {
+    let content_range = hm
+        .get(header::CONTENT_RANGE)
+        .ok_or(err::Error::BadRequest(format!("no Content-Range")))?
+        .to_str()
+        .map_err(|e| err::Error::BadRequest(format!("invalid Content-Range: {e}")))?;
+    parse_content_range(content_range)
+}
+
+/// Write `body` into temp blob `id` at `start..end`.
+async fn write_body(
+    store: &dyn AsBlobStore,
+    id: &str,
+    start: u64,
+    end: u64,
+    body: Body,
+) -> err::Result<()> {
+    let mut offset = start;
+    let mut buf = Vec::with_capacity(STREAM_BUFFER_SIZE);
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| err::Error::BadRequest(e.to_string()))?;
+        if offset + (buf.len() + chunk.len()) as u64 > end {
+            return Err(err::Error::BadRequest(format!(
+                "body is longer than Content-Range"
+            )));
+        }
+        buf.extend_from_slice(&chunk);
+        if buf.len() >= STREAM_BUFFER_SIZE {
+            let len = buf.len() as u64;
+            store
+                .write_at(id, offset, std::mem::take(&mut buf))
+                .await
+                .map_err(err::Error::from)?;
+            offset += len;
+        }
+    }
+    if !buf.is_empty() {
+        let len = buf.len() as u64;
+        store
+            .write_at(id, offset, buf)
+            .await
+            .map_err(err::Error::from)?;
+        offset += len;
+    }
+    if offset != end {
+        return Err(err::Error::BadRequest(format!(
+            "body is shorter than Content-Range"
+        )));
+    }
+    Ok(())
+}
+
+/// Parse a `Range` header against a representation of `length` bytes into `start..end`.
+///
+/// Follows RFC 7233: a header that is invalid, not in bytes, or asks for several ranges is
+/// ignored, and a range that does not overlap the representation is unsatisfiable.
+fn parse_range(s: &str, length: u64) -> err::Result<Option<(u64, u64)>> {
+    let spec = match s.trim().strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return Ok(None),
+    };
+    let (first, last) = match spec.split_once('-') {
+        Some(r) => r,
+        None => return Ok(None),
+    };
+    if first.is_empty() {
+        // Suffix range: the last `n` bytes.
+        let n: u64 = match last.parse() {
+            Ok(n) => n,
+            Err(_) => return Ok(None),
+        };
+        if n == 0 || length == 0 {
+            return Err(err::Error::RangeNotSatisfiable(length));
         }
+        return Ok(Some((length - std::cmp::min(n, length), length)));
+    }
+    let first: u64 = match first.parse() {
+        Ok(first) => first,
+        Err(_) => return Ok(None),
     };
-    let pair_v: Vec<Vec<&str>> = cookie
-        .split(';')
-        .into_iter()
-        .map(|pair| pair.split('=').collect::<Vec<&str>>())
-        .collect();
-    let mut cookie = HashMap::with_capacity(pair_v.len());
-    for pair in pair_v {
-        if pair.len() != 2 {
-            continue;
+    let end = if last.is_empty() {
+        length
+    } else {
+        match last.parse::<u64>() {
+            Ok(last) if last >= first => std::cmp::min(last.saturating_add(1), length),
+            _ => return Ok(None),
+        }
+    };
+    if first >= length {
+        return Err(err::Error::RangeNotSatisfiable(length));
+    }
+    Ok(Some((first, end)))
+}
+
+// Public
+/// Parse the `Cookie` headers as in RFC 6265 section 5.4.
+///
+/// Pairs are separated by `;` with optional whitespace, and a value may be quoted.
+pub fn get_cookie(hm: &HeaderMap) -> err::Result<HashMap<String, String>> {
+    let mut cookie = HashMap::new();
+    for value in hm.get_all(header::COOKIE) {
+        let value = value
+            .to_str()
+            .map_err(|e| err::Error::Other(e.to_string()))?;
+        for pair in value.split(';') {
+            let (name, value) = match pair.split_once('=') {
+                Some(r) => r,
+                None => continue,
+            };
+            let name = name.trim();
+            if name.is_empty() {
+                continue;
+            }
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value);
+            cookie
+                .entry(name.to_string())
+                .or_insert_with(|| value.to_string());
         }
-        cookie.insert(pair[0].to_string(), pair[1].to_string());
+    }
+    if cookie.is_empty() {
+        return Err(err::Error::Other(format!("no cookie")));
     }
     Ok(cookie)
 }
 
-pub async fn parse_auth(
+async fn get_config(dm: &dyn AsDataManager, name: &str) -> err::Result<String> {
+    dm.get(&Path::from_str(&format!("root->{name}")))
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+        .into_iter()
+        .next()
+        .ok_or(err::Error::Other(format!("no {name}")))
+}
+
+/// Fail if an upload of `length` bytes is larger than `max_upload_size`, unlimited if 0 or unset.
+async fn check_upload_size(dm: &dyn AsDataManager, length: u64) -> err::Result<()> {
+    let max_upload_size: u64 = match dm
+        .get(&Path::from_str("root->max_upload_size"))
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+        .first()
+    {
+        Some(s) => s
+            .parse()
+            .map_err(|_| err::Error::Other(format!("invalid max_upload_size")))?,
+        None => 0,
+    };
+    if max_upload_size > 0 && length > max_upload_size {
+        return Err(err::Error::PayloadTooLarge(max_upload_size));
+    }
+    Ok(())
+}
+
+async fn get_token_option(dm: Arc<dyn AsDataManager>) -> err::Result<crypto::TokenOption> {
+    Ok(crypto::TokenOption {
+        issuer: util::unescape(&get_config(dm.as_ref(), "token_issuer").await?),
+        audience: util::unescape(&get_config(dm.as_ref(), "token_audience").await?),
+        ttl: get_config(dm.as_ref(), "token_ttl")
+            .await?
+            .parse()
+            .map_err(|_| err::Error::Other(format!("invalid token_ttl")))?,
+        leeway: get_config(dm.as_ref(), "token_leeway")
+            .await?
+            .parse()
+            .map_err(|_| err::Error::Other(format!("invalid token_leeway")))?,
+    })
+}
+
+async fn verify_token(
     dm: Arc<dyn AsDataManager>,
-    cookie: &HashMap<String, String>,
+    keys: &KeyStore,
+    token: &str,
 ) -> err::Result<crypto::User> {
-    let token = match cookie.get("token") {
-        Some(r) => r,
-        None => {
-            return Err(err::Error::Other("no token".to_lowercase()));
-        }
+    let option = get_token_option(dm.clone()).await?;
+    let key_v = keys
+        .verifying()
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    // Trusted keys may have to be fetched, so only for tokens they can verify.
+    let public_v = if crypto::peek_alg(token)? == "EdDSA" {
+        keys.public_keys()
+            .await
+            .map_err(|e| err::Error::Other(e.to_string()))?
+    } else {
+        Vec::new()
     };
-    let key = dm
-        .get(&Path::from_str("root->key"))
+    let (jti, sub) = crypto::peek_subject(token)?;
+    let revoked = RevocationList::load(dm.clone(), &jti, &sub)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    let role_scope_v = role::find(dm, &sub)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+        .scope_v();
+    crypto::parse_token(&option, &key_v, &public_v, &revoked, &role_scope_v, token)
+}
+
+/// Authenticate a request by, in order, an `Authorization: Bearer` token, an `X-API-Key`
+/// header, or a `token` cookie.
+pub async fn authenticate(
+    dm: Arc<dyn AsDataManager>,
+    keys: &KeyStore,
+    hm: &HeaderMap,
+) -> err::Result<crypto::User> {
+    if let Some(value) = hm.get(header::AUTHORIZATION) {
+        let value = value
+            .to_str()
+            .map_err(|e| err::Error::NotLogin(e.to_string()))?;
+        let token = match value.split_once(' ') {
+            Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => token.trim(),
+            _ => return Err(err::Error::NotLogin(format!("unsupported Authorization"))),
+        };
+        return verify_token(dm, keys, token).await;
+    }
+    if let Some(value) = hm.get(API_KEY_HEADER) {
+        let value = value
+            .to_str()
+            .map_err(|e| err::Error::NotLogin(e.to_string()))?;
+        return match api_key::verify(dm.clone(), value)
+            .await
+            .map_err(|e| err::Error::Other(e.to_string()))?
+        {
+            Some(email) => Ok(crypto::User {
+                scope_v: role::find(dm, &email)
+                    .await
+                    .map_err(|e| err::Error::Other(e.to_string()))?
+                    .scope_v(),
+                email,
+                session: None,
+            }),
+            None => Err(err::Error::NotLogin(format!("invalid api key"))),
+        };
+    }
+    let cookie = get_cookie(hm).map_err(|e| err::Error::NotLogin(e.to_string()))?;
+    let token = cookie
+        .get("token")
+        .ok_or(err::Error::NotLogin(format!("no token")))?;
+    verify_token(dm, keys, token).await
+}
+
+/// Get the public keys of own Ed25519 keys as JWKS, for pools that trust this one.
+pub async fn jwks(keys: &KeyStore) -> err::Result<serde_json::Value> {
+    let key_v = keys
+        .own_public_keys()
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    Ok(jwks::to_jwks(&key_v))
+}
+
+/// Register a user with `auth.email` and `auth.password`.
+pub async fn register(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    auth: crypto::Auth,
+) -> err::Result<String> {
+    if !auth.email.contains('@') {
+        return Err(err::Error::BadRequest(format!(
+            "invalid email: {}",
+            auth.email
+        )));
+    }
+    if auth.password.len() < MIN_PASSWORD_LENGTH {
+        return Err(err::Error::BadRequest(format!(
+            "password is shorter than {MIN_PASSWORD_LENGTH}"
+        )));
+    }
+
+    // The graph has no unique constraint, so registrations are serialized here.
+    let _guard = REGISTER_LOCK.lock().await;
+    if user::find(dm.clone(), &auth.email)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+        .is_some()
+    {
+        return Err(err::Error::Conflict(format!(
+            "{} already exists",
+            auth.email
+        )));
+    }
+    let record = tokio::task::spawn_blocking(move || UserRecord::new(&auth.email, &auth.password))
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    record
+        .insert(dm, journal)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    log::info!("registered {}", record.email);
+    Ok(format!("success"))
+}
+
+#[derive(Serialize)]
+pub struct LoginInfo {
+    pub access_token: String,
+    /// Lifetime of the access token in seconds.
+    pub expires_in: u64,
+    pub refresh_token: String,
+}
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Deserialize)]
+pub struct LogoutRequest {
+    /// Log out of every session of the user.
+    #[serde(default)]
+    pub all: bool,
+}
+
+async fn get_refresh_ttl(dm: &dyn AsDataManager) -> err::Result<u64> {
+    get_config(dm, "refresh_ttl")
+        .await?
+        .parse()
+        .map_err(|_| err::Error::Other(format!("invalid refresh_ttl")))
+}
+
+/// Issue an access token of session `record` along with its refresh token `refresh_token`.
+async fn issue(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    keys: &KeyStore,
+    record: RefreshTokenRecord,
+    refresh_token: String,
+) -> err::Result<LoginInfo> {
+    let option = get_token_option(dm.clone()).await?;
+    let key = keys
+        .current()
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    let scope_v = role::find(dm.clone(), &record.owner)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+        .scope_v();
+    let access_token = crypto::gen_token(&option, &key, &record.owner, &record.id, &scope_v)
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    record
+        .insert(dm, journal)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    Ok(LoginInfo {
+        access_token,
+        expires_in: option.ttl,
+        refresh_token,
+    })
+}
+
+/// Verify `auth` and open a session for it.
+pub async fn login(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    keys: &KeyStore,
+    auth: crypto::Auth,
+) -> err::Result<LoginInfo> {
+    let record = user::find(dm.clone(), &auth.email)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    let password = auth.password.clone();
+    let is_valid =
+        tokio::task::spawn_blocking(move || user::verify_user(record.as_ref(), &password))
+            .await
+            .map_err(|e| err::Error::Other(e.to_string()))?;
+    if !is_valid {
+        return Err(err::Error::NotLogin(format!("invalid email or password")));
+    }
+    let refresh_ttl = get_refresh_ttl(dm.as_ref()).await?;
+    let (record, refresh_token) = RefreshTokenRecord::generate(&auth.email, refresh_ttl);
+    issue(dm, journal, keys, record, refresh_token).await
+}
+
+/// Exchange a refresh token for a new access token and the next refresh token of its session.
+pub async fn refresh(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    keys: &KeyStore,
+    rr: RefreshRequest,
+) -> err::Result<LoginInfo> {
+    // A refresh token is used once, even by concurrent requests.
+    let _guard = REFRESH_LOCK.lock().await;
+    let (node, record) = refresh_token::verify(dm.clone(), &rr.refresh_token, util::timestamp())
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+        .ok_or(err::Error::NotLogin(format!("invalid refresh token")))?;
+    // A revoked or removed user must not get past the revocation with a newer `iat`.
+    let revoked = RevocationList::load(dm.clone(), &record.id, &record.owner)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    let is_user = user::find(dm.clone(), &record.owner)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+        .is_some();
+    if !is_user || revoked.is_revoked(&record.id, &record.owner, record.created_at) {
+        refresh_token::remove_session(dm, journal, &record.id)
+            .await
+            .map_err(|e| err::Error::Other(e.to_string()))?;
+        return Err(err::Error::NotLogin(format!("revoked refresh token")));
+    }
+    refresh_token::remove(dm.clone(), journal, &node)
         .await
         .map_err(|e| err::Error::Other(e.to_string()))?;
-    if key.is_empty() {
-        return Err(err::Error::Other("no key".to_string()));
+    let refresh_ttl = get_refresh_ttl(dm.as_ref()).await?;
+    let (record, refresh_token) =
+        RefreshTokenRecord::rotate(&record.id, &record.owner, refresh_ttl);
+    issue(dm, journal, keys, record, refresh_token).await
+}
+
+/// End the session of `auth`, or with `lr.all` every session of its user.
+///
+/// Access tokens already issued are revoked until they expire.
+pub async fn logout(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    auth: &crypto::User,
+    lr: LogoutRequest,
+) -> err::Result<String> {
+    log::info!("email: {}", auth.email);
+
+    let session = match (&auth.session, lr.all) {
+        (_, true) => None,
+        (Some(session), false) => Some(session),
+        (None, false) => return Err(err::Error::NotFound(format!("no session to log out"))),
+    };
+    for (node, record) in refresh_token::list_by_owner(dm.clone(), &auth.email)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+    {
+        if session.is_none_or(|session| &record.id == session) {
+            refresh_token::remove(dm.clone(), journal, &node)
+                .await
+                .map_err(|e| err::Error::Other(e.to_string()))?;
+        }
     }
-    crypto::parse_token(&key[0], token)
+    let option = get_token_option(dm.clone()).await?;
+    let expires_at = util::timestamp() + option.ttl + option.leeway;
+    match session {
+        Some(session) => revocation::revoke_session(dm, journal, session, expires_at).await,
+        None => revocation::revoke_subject(dm, journal, &auth.email, expires_at).await,
+    }
+    .map_err(|e| err::Error::Other(e.to_string()))?;
+    Ok(format!("success"))
 }
 
 #[derive(Deserialize)]
@@ -67,112 +781,2290 @@ pub struct DataSlice {
     pub offset: u64,
     pub slice_value: Vec<u8>,
     pub length: u64,
+    /// Only used by drop boxes, files uploaded otherwise are named by md5.
+    #[serde(default)]
+    pub name: String,
 }
 
 pub async fn upload(
     dm: Arc<dyn AsDataManager>,
-    hm: &HeaderMap,
+    journal: &Journal,
+    store: Arc<dyn AsBlobStore>,
+    sessions: Arc<SessionStore>,
+    auth: &crypto::User,
     ds: DataSlice,
 ) -> err::Result<String> {
-    let cookie = get_cookie(hm).map_err(|e| err::Error::NotLogin(e.to_string()))?;
-    let auth = parse_auth(dm.clone(), &cookie)
-        .await
-        .map_err(|e| err::Error::NotLogin(e.to_string()))?;
     log::info!("email: {}", auth.email);
+    auth.require(Scope::Upload)?;
 
-    if ds.offset + ds.slice_value.len() as u64 > ds.length {
-        return Err(err::Error::Other(format!("out of bound")));
-    }
+    let end = ds
+        .offset
+        .checked_add(ds.slice_value.len() as u64)
+        .filter(|end| *end <= ds.length)
+        .ok_or(err::Error::BadRequest(format!("out of bound")))?;
+    check_upload_size(dm.as_ref(), ds.length).await?;
 
-    let temp_name = format!("{}.temp", ds.md5);
-    match fs::File::open(&temp_name) {
-        Ok(mut f) => {
-            let length = f
-                .metadata()
-                .map_err(|e| err::Error::Other(e.to_string()))?
-                .len();
-            if ds.offset > length {
-                return Err(err::Error::Other(format!("out of bound")));
-            }
-            f.seek(std::io::SeekFrom::Current(ds.offset as i64))
-                .map_err(|e| err::Error::Other(e.to_string()))?;
-            f.write_all(&ds.slice_value)
-                .map_err(|e| err::Error::Other(e.to_string()))?;
-            drop(f);
-            if ds.offset + ds.slice_value.len() as u64 == ds.length {
-                fs::rename(&temp_name, ds.md5).map_err(|e| err::Error::Other(e.to_string()))?;
-            }
-            Ok(format!("success"))
-        }
-        Err(e) => match e.kind() {
-            std::io::ErrorKind::NotFound => {
-                let mut f =
-                    fs::File::create(&temp_name).map_err(|e| err::Error::Other(e.to_string()))?;
-                if ds.offset > 0 {
-                    return Err(err::Error::Other(format!("out of bound")));
-                }
-                f.write_all(&ds.slice_value)
-                    .map_err(|e| err::Error::Other(e.to_string()))?;
-                drop(f);
-                if ds.offset + ds.slice_value.len() as u64 == ds.length {
-                    fs::rename(&temp_name, ds.md5).map_err(|e| err::Error::Other(e.to_string()))?;
-                }
-                Ok(format!("success"))
-            }
-            _ => Err(err::Error::Other(e.to_string())),
-        },
+    let md5 = ds.md5.to_lowercase();
+    if !util::is_md5(&md5) {
+        return Err(err::Error::BadRequest(format!("invalid md5: {}", ds.md5)));
     }
-}
 
-#[derive(Deserialize)]
-pub struct FileRequest {
-    md5: String,
-    start: Option<u64>,
-    size: Option<u64>,
+    if is_md5_uploaded(dm.clone(), &sessions, &md5, ds.length, &auth.email).await? {
+        return Ok(format!("success"));
+    }
+    let session = open_md5_session(store.as_ref(), &sessions, &md5, ds.length, &auth.email).await?;
+    if !session.is_complete() {
+        store
+            .write_at(&session.id, ds.offset, ds.slice_value)
+            .await
+            .map_err(err::Error::from)?;
+    }
+    commit_range(
+        dm.clone(),
+        journal,
+        store.as_ref(),
+        &sessions,
+        &session.id,
+        ds.offset,
+        end,
+    )
+    .await?;
+    Ok(format!("success"))
 }
 
-pub async fn download(
+/// Stream a raw `body` into the temp blob of the caller's upload of `md5` at the offset given by
+/// `Content-Range`.
+pub async fn upload_stream(
     dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    store: Arc<dyn AsBlobStore>,
+    sessions: Arc<SessionStore>,
+    auth: &crypto::User,
     hm: &HeaderMap,
-    fr: FileRequest,
-) -> err::Result<DataSlice> {
-    let cookie = get_cookie(hm).map_err(|e| err::Error::NotLogin(e.to_string()))?;
-    let auth = parse_auth(dm.clone(), &cookie)
-        .await
-        .map_err(|e| err::Error::NotLogin(e.to_string()))?;
+    md5: String,
+    body: Body,
+) -> err::Result<String> {
     log::info!("email: {}", auth.email);
+    auth.require(Scope::Upload)?;
 
-    let start = match fr.start {
-        Some(start) => start,
-        None => 0,
-    };
-    let mut size = match fr.size {
-        Some(size) => size,
-        None => 1024,
-    };
+    let (start, end, length) = get_content_range(hm)?;
+    check_upload_size(dm.as_ref(), length).await?;
 
-    let mut f = fs::File::open(&fr.md5).map_err(|e| err::Error::Other(e.to_string()))?;
-    let length = f
-        .metadata()
-        .map_err(|e| err::Error::Other(e.to_string()))?
-        .len();
-    size = std::cmp::min(size, length - start);
-    let mut slice_value = Vec::with_capacity(length as usize);
-    if start == 0 && length < 1024 * 1024 {
-        f.read_to_end(&mut slice_value)
-            .map_err(|e| err::Error::Other(e.to_string()))?;
-    } else {
-        slice_value.resize(size as usize, 0);
-        f.seek(std::io::SeekFrom::Current(start as i64))
-            .map_err(|e| err::Error::Other(e.to_string()))?;
-        f.read_exact(slice_value.by_ref())
-            .map_err(|e| err::Error::Other(e.to_string()))?;
+    let md5 = md5.to_lowercase();
+    if !util::is_md5(&md5) {
+        return Err(err::Error::BadRequest(format!("invalid md5: {md5}")));
     }
 
-    Ok(DataSlice {
-        md5: fr.md5,
-        offset: start,
-        slice_value,
-        length,
-    })
+    if is_md5_uploaded(dm.clone(), &sessions, &md5, length, &auth.email).await? {
+        return Ok(format!("success"));
+    }
+    let session = open_md5_session(store.as_ref(), &sessions, &md5, length, &auth.email).await?;
+    if !session.is_complete() {
+        write_body(store.as_ref(), &session.id, start, end, body).await?;
+    }
+    commit_range(
+        dm.clone(),
+        journal,
+        store.as_ref(),
+        &sessions,
+        &session.id,
+        start,
+        end,
+    )
+    .await?;
+    Ok(format!("success"))
+}
+
+#[derive(Deserialize)]
+pub struct DropBoxRequest {
+    #[serde(default)]
+    pub title: String,
+    /// Maximum length of each file, unlimited if absent.
+    pub max_size: Option<u64>,
+    /// Maximum number of files, unlimited if absent.
+    pub max_files: Option<u64>,
+    /// Lifetime of the box in seconds, forever if absent.
+    pub expires_in: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct DropBoxInfo {
+    pub slug: String,
+    /// Path to upload to, relative to the pool.
+    pub url: String,
+    pub title: String,
+    /// 0 if unlimited.
+    pub max_size: u64,
+    /// 0 if unlimited.
+    pub max_files: u64,
+    pub file_count: u64,
+    /// 0 if the box never expires.
+    pub expires_at: u64,
+    pub created_at: u64,
+}
+
+impl DropBoxInfo {
+    fn new(path: &str, record: DropBoxRecord) -> Self {
+        Self {
+            url: format!("{path}/d/{}/upload", record.slug),
+            slug: record.slug,
+            title: record.title,
+            max_size: record.max_size,
+            max_files: record.max_files,
+            file_count: record.file_count,
+            expires_at: record.expires_at,
+            created_at: record.created_at,
+        }
+    }
+}
+
+/// Create a drop box that uploads into the space of the caller.
+pub async fn create_drop_box(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    auth: &crypto::User,
+    dr: DropBoxRequest,
+) -> err::Result<DropBoxInfo> {
+    log::info!("email: {}", auth.email);
+    auth.require(Scope::Upload)?;
+
+    let expires_at = match dr.expires_in {
+        Some(0) => return Err(err::Error::BadRequest(format!("expires_in is 0"))),
+        Some(expires_in) => util::timestamp().saturating_add(expires_in),
+        None => 0,
+    };
+    if dr.max_size == Some(0) || dr.max_files == Some(0) {
+        return Err(err::Error::BadRequest(format!(
+            "max_size or max_files is 0"
+        )));
+    }
+    let record = DropBoxRecord::new(
+        &auth.email,
+        &dr.title,
+        dr.max_size.unwrap_or(0),
+        dr.max_files.unwrap_or(0),
+        expires_at,
+    );
+    record
+        .insert(dm.clone(), journal)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    let path = get_config(dm.as_ref(), "path").await?;
+    Ok(DropBoxInfo::new(&path, record))
+}
+
+pub async fn list_drop_boxes(
+    dm: Arc<dyn AsDataManager>,
+    auth: &crypto::User,
+) -> err::Result<Vec<DropBoxInfo>> {
+    let path = get_config(dm.as_ref(), "path").await?;
+    Ok(drop_box::list_by_owner(dm, &auth.email)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+        .into_iter()
+        .map(|(_, record)| DropBoxInfo::new(&path, record))
+        .collect())
+}
+
+/// Close drop box `slug` of the caller. Uploads already started through it can not finish.
+pub async fn delete_drop_box(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    auth: &crypto::User,
+    slug: String,
+) -> err::Result<String> {
+    log::info!("email: {}", auth.email);
+
+    let node = drop_box::find(dm.clone(), &slug)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+        .filter(|(_, record)| record.owner == auth.email)
+        .map(|(node, _)| node)
+        .ok_or(err::Error::NotFound(format!("no drop box {slug}")))?;
+    drop_box::remove(dm, journal, &node)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    Ok(format!("success"))
+}
+
+/// Upload a chunk through drop box `slug` without authentication, as its owner.
+///
+/// Each file gets a session of its own in the box, which holds room for the file from its first
+/// chunk on. The file counts against the box once complete, and an abandoned file frees its room
+/// when its session expires.
+pub async fn upload_drop_box(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    store: Arc<dyn AsBlobStore>,
+    sessions: Arc<SessionStore>,
+    slug: String,
+    ds: DataSlice,
+) -> err::Result<String> {
+    let not_found = || err::Error::NotFound(format!("no drop box {slug}"));
+    let (_, record) = drop_box::find(dm.clone(), &slug)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+        .ok_or_else(not_found)?;
+    if record.is_expired(util::timestamp()) {
+        return Err(not_found());
+    }
+    if !record.fits(ds.length) {
+        return Err(err::Error::PayloadTooLarge(record.max_size));
+    }
+    check_upload_size(dm.as_ref(), ds.length).await?;
+    let end = ds
+        .offset
+        .checked_add(ds.slice_value.len() as u64)
+        .filter(|end| *end <= ds.length)
+        .ok_or(err::Error::BadRequest(format!("out of bound")))?;
+    let md5 = ds.md5.to_lowercase();
+    if !util::is_md5(&md5) {
+        return Err(err::Error::BadRequest(format!("invalid md5: {}", ds.md5)));
+    }
+    if !role::find(dm.clone(), &record.owner)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+        .scope_v()
+        .contains(&Scope::Upload)
+    {
+        return Err(err::Error::Forbidden(format!(
+            "{} lacks scope {}",
+            record.owner,
+            Scope::Upload
+        )));
+    }
+
+    let id = format!("{slug}{md5}");
+    let session = {
+        // Room is checked and taken at once, so concurrent files can not overfill the box.
+        let _guard = DROP_BOX_LOCK.lock().await;
+        match sessions
+            .get(&id)
+            .await
+            .map_err(|e| err::Error::Other(e.to_string()))?
+        {
+            Some(session) => session,
+            None => {
+                let (_, record) = find_drop_box_room(dm.clone(), &sessions, &slug).await?;
+                open_session(
+                    store.as_ref(),
+                    &sessions,
+                    &id,
+                    &md5,
+                    ds.length,
+                    &ds.name,
+                    &record.owner,
+                )
+                .await?;
+                let session = sessions
+                    .update(&id, |session| session.drop_box = slug.clone())
+                    .await
+                    .map_err(|e| err::Error::Other(e.to_string()))?
+                    .ok_or(err::Error::NotFound(format!("no session {id}")))?;
+                log::info!("drop box {slug} of {} gets {md5}", record.owner);
+                session
+            }
+        }
+    };
+    if session.length != ds.length {
+        return Err(err::Error::Conflict(format!(
+            "length {} does not match {}",
+            ds.length, session.length
+        )));
+    }
+    if !session.is_complete() {
+        store
+            .write_at(&id, ds.offset, ds.slice_value)
+            .await
+            .map_err(err::Error::from)?;
+    }
+    commit_range(dm, journal, store.as_ref(), &sessions, &id, ds.offset, end).await?;
+    Ok(format!("success"))
+}
+
+#[derive(Deserialize)]
+pub struct SessionRequest {
+    pub md5: String,
+    /// Only used by instant upload, to pick the blob when md5s collide.
+    pub sha256: Option<String>,
+    pub length: u64,
+    pub name: String,
+}
+
+#[derive(Serialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub md5: String,
+    pub length: u64,
+    pub name: String,
+    /// Committed byte ranges as `[start, end)` pairs.
+    pub range_v: Vec<(u64, u64)>,
+    pub expires_at: u64,
+}
+
+impl SessionInfo {
+    fn new(session: UploadSession, ttl: u64) -> Self {
+        Self {
+            range_v: session.range_v,
+            expires_at: session.updated_at + ttl,
+            id: session.id,
+            md5: session.md5,
+            length: session.length,
+            name: session.name,
+        }
+    }
+}
+
+/// Get session `id` of `owner`.
+async fn get_own_session(
+    sessions: &SessionStore,
+    id: &str,
+    owner: &str,
+) -> err::Result<UploadSession> {
+    match sessions
+        .get(id)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+    {
+        Some(session) if session.owner == owner => Ok(session),
+        _ => Err(err::Error::NotFound(format!("no session {id}"))),
+    }
+}
+
+pub async fn create_session(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    store: Arc<dyn AsBlobStore>,
+    sessions: Arc<SessionStore>,
+    auth: &crypto::User,
+    sr: SessionRequest,
+) -> err::Result<SessionInfo> {
+    log::info!("email: {}", auth.email);
+    auth.require(Scope::Upload)?;
+
+    let md5 = sr.md5.to_lowercase();
+    if !util::is_md5(&md5) {
+        return Err(err::Error::BadRequest(format!("invalid md5: {}", sr.md5)));
+    }
+    check_upload_size(dm.as_ref(), sr.length).await?;
+
+    let session = sessions
+        .create(&md5, sr.length, &sr.name, &auth.email)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    store
+        .begin(&session.id, session.length)
+        .await
+        .map_err(err::Error::from)?;
+    if session.length == 0 {
+        let session = commit_range(
+            dm.clone(),
+            journal,
+            store.as_ref(),
+            &sessions,
+            &session.id,
+            0,
+            0,
+        )
+        .await?;
+        return Ok(SessionInfo::new(session, sessions.ttl()));
+    }
+    Ok(SessionInfo::new(session, sessions.ttl()))
+}
+
+pub async fn get_session(
+    sessions: Arc<SessionStore>,
+    auth: &crypto::User,
+    id: String,
+) -> err::Result<SessionInfo> {
+    auth.require(Scope::Upload)?;
+    let session = get_own_session(&sessions, &id, &auth.email).await?;
+    Ok(SessionInfo::new(session, sessions.ttl()))
+}
+
+pub async fn delete_session(
+    store: Arc<dyn AsBlobStore>,
+    sessions: Arc<SessionStore>,
+    auth: &crypto::User,
+    id: String,
+) -> err::Result<String> {
+    log::info!("email: {}", auth.email);
+    auth.require(Scope::Upload)?;
+
+    let session = get_own_session(&sessions, &id, &auth.email).await?;
+    sessions
+        .remove(&session.id)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    store
+        .abort(&session.id)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    Ok(format!("success"))
+}
+
+/// Stream a raw `body` into session `id` at the offset given by `Content-Range`.
+///
+/// The session is finalized once all of its bytes are committed.
+pub async fn upload_session(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    store: Arc<dyn AsBlobStore>,
+    sessions: Arc<SessionStore>,
+    auth: &crypto::User,
+    hm: &HeaderMap,
+    id: String,
+    body: Body,
+) -> err::Result<SessionInfo> {
+    log::info!("email: {}", auth.email);
+    auth.require(Scope::Upload)?;
+
+    let (start, end, length) = get_content_range(hm)?;
+    let session = get_own_session(&sessions, &id, &auth.email).await?;
+    if length != session.length {
+        return Err(err::Error::Conflict(format!(
+            "length {length} does not match {}",
+            session.length
+        )));
+    }
+    if !session.is_complete() {
+        write_body(store.as_ref(), &session.id, start, end, body).await?;
+    }
+    let session = commit_range(
+        dm.clone(),
+        journal,
+        store.as_ref(),
+        &sessions,
+        &session.id,
+        start,
+        end,
+    )
+    .await?;
+    Ok(SessionInfo::new(session, sessions.ttl()))
+}
+
+#[derive(Deserialize)]
+pub struct FileRequest {
+    md5: Option<String>,
+    sha256: Option<String>,
+    start: Option<u64>,
+    size: Option<u64>,
+    /// Expiry of a presigned URL.
+    expires: Option<u64>,
+    /// Signer of a presigned URL.
+    sub: Option<String>,
+    kid: Option<String>,
+    signature: Option<String>,
+}
+
+impl FileRequest {
+    /// Whether this is the request of a presigned URL.
+    pub fn is_presigned(&self) -> bool {
+        self.signature.is_some()
+    }
+}
+
+/// Bytes `start..end` of a blob of `length` bytes.
+pub struct DataStream {
+    pub key: String,
+    pub start: u64,
+    pub end: u64,
+    pub length: u64,
+    pub reader: BlobReader,
+}
+
+/// Open blob `key` for the range of the `Range` header, or else of `start` and `size`.
+async fn read_blob(
+    store: &dyn AsBlobStore,
+    key: String,
+    hm: &HeaderMap,
+    start: Option<u64>,
+    size: Option<u64>,
+) -> err::Result<DataStream> {
+    let length = store
+        .stat(&key)
+        .await
+        .map_err(|e| err::Error::from_blob_read(e, &key))?
+        .ok_or(err::Error::NotFound(format!("{key} not found")))?;
+
+    let range = match hm.get(header::RANGE).and_then(|v| v.to_str().ok()) {
+        Some(range) => parse_range(range, length)?,
+        None => match (start, size) {
+            (None, None) => None,
+            (start, size) => {
+                let start = start.unwrap_or(0);
+                if start >= length && !(start == 0 && length == 0) {
+                    return Err(err::Error::RangeNotSatisfiable(length));
+                }
+                let end = match size {
+                    Some(size) => std::cmp::min(start.saturating_add(size), length),
+                    None => length,
+                };
+                if end <= start && length > 0 {
+                    return Err(err::Error::RangeNotSatisfiable(length));
+                }
+                Some((start, end))
+            }
+        },
+    };
+    let (start, end) = range.unwrap_or((0, length));
+    let reader = store
+        .read_range(&key, start, end)
+        .await
+        .map_err(|e| err::Error::from_blob_read(e, &key))?;
+
+    Ok(DataStream {
+        key,
+        start,
+        end,
+        length,
+        reader,
+    })
+}
+
+pub async fn download(
+    dm: Arc<dyn AsDataManager>,
+    store: Arc<dyn AsBlobStore>,
+    auth: &crypto::User,
+    hm: &HeaderMap,
+    fr: FileRequest,
+) -> err::Result<DataStream> {
+    log::info!("email: {}", auth.email);
+    auth.require(Scope::Download)?;
+
+    let (md5, sha256) = parse_digests(fr.md5, fr.sha256)?;
+    let key = find_key(
+        dm.clone(),
+        store.as_ref(),
+        md5.as_deref(),
+        sha256.as_deref(),
+    )
+    .await?
+    .ok_or(err::Error::NotFound(format!("file not found")))?;
+    // Blobs are shared by digest, so only a record the caller may read grants access.
+    if find_files(dm, auth, &key, Right::Read).await?.is_empty() {
+        return Err(err::Error::NotFound(format!("no file {key}")));
+    }
+    read_blob(store.as_ref(), key, hm, fr.start, fr.size).await
+}
+
+#[derive(Deserialize)]
+pub struct PresignRequest {
+    pub md5: Option<String>,
+    pub sha256: Option<String>,
+    /// Lifetime of the URL in seconds.
+    pub expires_in: Option<u64>,
+    pub start: Option<u64>,
+    pub size: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct PresignedUrl {
+    /// Path and query of the URL, relative to the pool.
+    pub url: String,
+    pub expires_at: u64,
+}
+
+/// The message a presigned URL signs.
+fn presign_message(
+    md5: Option<&str>,
+    sha256: Option<&str>,
+    sub: &str,
+    expires: u64,
+    start: Option<u64>,
+    size: Option<u64>,
+) -> String {
+    let digest = match (md5, sha256) {
+        (Some(md5), Some(sha256)) => format!("md5={md5}&sha256={sha256}"),
+        (Some(md5), None) => format!("md5={md5}"),
+        (None, Some(sha256)) => format!("sha256={sha256}"),
+        (None, None) => String::new(),
+    };
+    let start = start.map(|start| start.to_string()).unwrap_or_default();
+    let size = size.map(|size| size.to_string()).unwrap_or_default();
+    format!("{digest}\n{sub}\n{expires}\n{start}\n{size}")
+}
+
+/// Mint a download URL of a file the caller may read, valid without a token until it expires.
+pub async fn presign(
+    dm: Arc<dyn AsDataManager>,
+    store: Arc<dyn AsBlobStore>,
+    keys: &KeyStore,
+    auth: &crypto::User,
+    pr: PresignRequest,
+) -> err::Result<PresignedUrl> {
+    log::info!("email: {}", auth.email);
+    auth.require(Scope::Download)?;
+
+    let expires_in = pr.expires_in.unwrap_or(DEFAULT_PRESIGN_TTL);
+    if expires_in == 0 || expires_in > MAX_PRESIGN_TTL {
+        return Err(err::Error::BadRequest(format!(
+            "expires_in is not in 1..={MAX_PRESIGN_TTL}"
+        )));
+    }
+    let (md5, sha256) = parse_digests(pr.md5, pr.sha256)?;
+    let key = find_key(
+        dm.clone(),
+        store.as_ref(),
+        md5.as_deref(),
+        sha256.as_deref(),
+    )
+    .await?
+    .ok_or(err::Error::NotFound(format!("file not found")))?;
+    if find_files(dm.clone(), auth, &key, Right::Read)
+        .await?
+        .is_empty()
+    {
+        return Err(err::Error::NotFound(format!("no file {key}")));
+    }
+
+    let expires_at = util::timestamp() + expires_in;
+    let message = presign_message(
+        md5.as_deref(),
+        sha256.as_deref(),
+        &auth.email,
+        expires_at,
+        pr.start,
+        pr.size,
+    );
+    let signing = keys
+        .current()
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    let signature =
+        crypto::sign_url(&signing, &message).map_err(|e| err::Error::Other(e.to_string()))?;
+
+    let mut param_v = Vec::new();
+    if let Some(md5) = &md5 {
+        param_v.push(format!("md5={md5}"));
+    }
+    if let Some(sha256) = &sha256 {
+        param_v.push(format!("sha256={sha256}"));
+    }
+    if let Some(start) = pr.start {
+        param_v.push(format!("start={start}"));
+    }
+    if let Some(size) = pr.size {
+        param_v.push(format!("size={size}"));
+    }
+    param_v.push(format!("sub={}", util::escape(&auth.email)));
+    param_v.push(format!("expires={expires_at}"));
+    param_v.push(format!("kid={}", signing.kid));
+    param_v.push(format!("signature={signature}"));
+    let path = get_config(dm.as_ref(), "path").await?;
+    Ok(PresignedUrl {
+        url: format!("{path}/download?{}", param_v.join("&")),
+        expires_at,
+    })
+}
+
+/// Download through a presigned URL, which stands in for authentication.
+///
+/// A URL signed with a range serves exactly that range, whatever the `Range` header asks. The
+/// signer must still be allowed to read the file.
+pub async fn download_presigned(
+    dm: Arc<dyn AsDataManager>,
+    store: Arc<dyn AsBlobStore>,
+    keys: &KeyStore,
+    hm: &HeaderMap,
+    fr: FileRequest,
+) -> err::Result<DataStream> {
+    let (md5, sha256) = parse_digests(fr.md5, fr.sha256)?;
+    let expires = fr
+        .expires
+        .ok_or(err::Error::NotLogin(format!("no expires")))?;
+    let kid = fr.kid.ok_or(err::Error::NotLogin(format!("no kid")))?;
+    let sub = fr.sub.ok_or(err::Error::NotLogin(format!("no sub")))?;
+    let signature = fr.signature.unwrap_or_default();
+    let message = presign_message(
+        md5.as_deref(),
+        sha256.as_deref(),
+        &sub,
+        expires,
+        fr.start,
+        fr.size,
+    );
+    let key_v = keys
+        .verifying()
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    crypto::verify_url(&key_v, &kid, &message, &signature)?;
+    if util::timestamp() > expires {
+        return Err(err::Error::TokenExpired(expires));
+    }
+    log::info!("presigned: {message:?}");
+
+    let key = find_key(
+        dm.clone(),
+        store.as_ref(),
+        md5.as_deref(),
+        sha256.as_deref(),
+    )
+    .await?
+    .ok_or(err::Error::NotFound(format!("file not found")))?;
+    // Unsharing a file or demoting its signer ends the URL as well.
+    let signer = crypto::User {
+        scope_v: role::find(dm.clone(), &sub)
+            .await
+            .map_err(|e| err::Error::Other(e.to_string()))?
+            .scope_v(),
+        email: sub,
+        session: None,
+    };
+    signer.require(Scope::Download)?;
+    if find_files(dm, &signer, &key, Right::Read).await?.is_empty() {
+        return Err(err::Error::NotFound(format!("no file {key}")));
+    }
+    if fr.start.is_some() || fr.size.is_some() {
+        read_blob(store.as_ref(), key, &HeaderMap::new(), fr.start, fr.size).await
+    } else {
+        read_blob(store.as_ref(), key, hm, None, None).await
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LinkRequest {
+    pub md5: Option<String>,
+    pub sha256: Option<String>,
+    pub password: Option<String>,
+    /// Lifetime of the link in seconds, forever if absent.
+    pub expires_in: Option<u64>,
+    /// Unlimited if absent.
+    pub max_downloads: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct LinkInfo {
+    pub slug: String,
+    /// Path of the link, relative to the pool.
+    pub url: String,
+    pub name: String,
+    pub has_password: bool,
+    /// 0 if the link never expires.
+    pub expires_at: u64,
+    /// 0 if downloads are unlimited.
+    pub max_downloads: u64,
+    pub download_count: u64,
+    pub created_at: u64,
+}
+
+impl LinkInfo {
+    fn new(path: &str, record: ShareLinkRecord) -> Self {
+        Self {
+            url: format!("{path}/s/{}", record.slug),
+            has_password: !record.password_hash.is_empty(),
+            slug: record.slug,
+            name: record.name,
+            expires_at: record.expires_at,
+            max_downloads: record.max_downloads,
+            download_count: record.download_count,
+            created_at: record.created_at,
+        }
+    }
+}
+
+/// Create a public link to a file the caller may read.
+pub async fn create_link(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    store: Arc<dyn AsBlobStore>,
+    auth: &crypto::User,
+    lr: LinkRequest,
+) -> err::Result<LinkInfo> {
+    log::info!("email: {}", auth.email);
+    auth.require(Scope::Download)?;
+
+    let (md5, sha256) = parse_digests(lr.md5, lr.sha256)?;
+    let key = find_key(
+        dm.clone(),
+        store.as_ref(),
+        md5.as_deref(),
+        sha256.as_deref(),
+    )
+    .await?
+    .ok_or(err::Error::NotFound(format!("file not found")))?;
+    let (_, file) = find_files(dm.clone(), auth, &key, Right::Read)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or(err::Error::NotFound(format!("no file {key}")))?;
+    let expires_at = match lr.expires_in {
+        Some(0) => return Err(err::Error::BadRequest(format!("expires_in is 0"))),
+        Some(expires_in) => util::timestamp().saturating_add(expires_in),
+        None => 0,
+    };
+    if lr.max_downloads == Some(0) {
+        return Err(err::Error::BadRequest(format!("max_downloads is 0")));
+    }
+    let password = lr.password.unwrap_or_default();
+    let owner = auth.email.clone();
+    let max_downloads = lr.max_downloads.unwrap_or(0);
+    let record = tokio::task::spawn_blocking(move || {
+        ShareLinkRecord::new(
+            &owner,
+            &key,
+            &file.name,
+            &password,
+            expires_at,
+            max_downloads,
+        )
+    })
+    .await
+    .map_err(|e| err::Error::Other(e.to_string()))?
+    .map_err(|e| err::Error::Other(e.to_string()))?;
+    record
+        .insert(dm.clone(), journal)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    let path = get_config(dm.as_ref(), "path").await?;
+    Ok(LinkInfo::new(&path, record))
+}
+
+pub async fn list_links(
+    dm: Arc<dyn AsDataManager>,
+    auth: &crypto::User,
+) -> err::Result<Vec<LinkInfo>> {
+    let path = get_config(dm.as_ref(), "path").await?;
+    Ok(share_link::list_by_owner(dm, &auth.email)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+        .into_iter()
+        .map(|(_, record)| LinkInfo::new(&path, record))
+        .collect())
+}
+
+/// Revoke link `slug` of the caller.
+pub async fn delete_link(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    auth: &crypto::User,
+    slug: String,
+) -> err::Result<String> {
+    log::info!("email: {}", auth.email);
+
+    let node = share_link::find(dm.clone(), &slug)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+        .filter(|(_, record)| record.owner == auth.email)
+        .map(|(node, _)| node)
+        .ok_or(err::Error::NotFound(format!("no link {slug}")))?;
+    share_link::remove(dm, journal, &node)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    Ok(format!("success"))
+}
+
+/// Body of `POST /s/:slug`. The password is never taken from the query, which ends up in logs.
+#[derive(Deserialize, Default)]
+pub struct LinkAccess {
+    /// Used if there is no `X-Link-Password` header.
+    pub password: Option<String>,
+}
+
+/// Fail with `TooManyRequests` while share link `slug` has used up its wrong passwords.
+fn check_link_failures(slug: &str, now: u64) -> err::Result<()> {
+    let failure_mp = LINK_FAILURE_MP.lock().unwrap();
+    match failure_mp.get(slug) {
+        Some((start, count))
+            if now < start + LINK_FAILURE_WINDOW && *count >= MAX_LINK_FAILURES =>
+        {
+            Err(err::Error::TooManyRequests(
+                start + LINK_FAILURE_WINDOW - now,
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Count a wrong password of share link `slug`.
+fn add_link_failure(slug: &str, now: u64) {
+    let mut failure_mp = LINK_FAILURE_MP.lock().unwrap();
+    failure_mp.retain(|_, (start, _)| now < *start + LINK_FAILURE_WINDOW);
+    failure_mp.entry(slug.to_string()).or_insert((now, 0)).1 += 1;
+}
+
+/// Download through link `slug` without authentication, returning the data and the file name.
+///
+/// The creator of the link must still be able to read the file. Every request but `HEAD`
+/// counts as a download, whatever its range, as each may fetch a part of the file.
+pub async fn download_link(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    store: Arc<dyn AsBlobStore>,
+    method: &Method,
+    hm: &HeaderMap,
+    slug: String,
+    la: LinkAccess,
+) -> err::Result<(DataStream, String)> {
+    let not_found = || err::Error::NotFound(format!("no link {slug}"));
+    let (_, record) = share_link::find(dm.clone(), &slug)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+        .ok_or_else(not_found)?;
+    if !record.is_valid(util::timestamp()) {
+        return Err(not_found());
+    }
+    if !record.password_hash.is_empty() {
+        check_link_failures(&slug, util::timestamp())?;
+        let password = match hm.get(LINK_PASSWORD_HEADER) {
+            Some(value) => value
+                .to_str()
+                .map_err(|e| err::Error::NotLogin(e.to_string()))?
+                .to_string(),
+            None => la.password.unwrap_or_default(),
+        };
+        let password_hash = record.password_hash.clone();
+        let is_valid =
+            tokio::task::spawn_blocking(move || user::verify_password(&password_hash, &password))
+                .await
+                .map_err(|e| err::Error::Other(e.to_string()))?;
+        if !is_valid {
+            add_link_failure(&slug, util::timestamp());
+            return Err(err::Error::NotLogin(format!("invalid password")));
+        }
+    }
+    let owner = crypto::User {
+        scope_v: role::find(dm.clone(), &record.owner)
+            .await
+            .map_err(|e| err::Error::Other(e.to_string()))?
+            .scope_v(),
+        email: record.owner.clone(),
+        session: None,
+    };
+    if !owner.has(Scope::Download)
+        || find_files(dm.clone(), &owner, &record.key, Right::Read)
+            .await?
+            .is_empty()
+    {
+        return Err(not_found());
+    }
+
+    // The count is checked and raised at once, so concurrent requests can not exceed it.
+    let _guard = SHARE_LINK_LOCK.lock().await;
+    let (node, record) = share_link::find(dm.clone(), &slug)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+        .ok_or_else(not_found)?;
+    if !record.is_valid(util::timestamp()) {
+        return Err(not_found());
+    }
+    let ds = read_blob(store.as_ref(), record.key.clone(), hm, None, None).await?;
+    if method != Method::HEAD {
+        share_link::set_download_count(dm, journal, &node, record.download_count + 1)
+            .await
+            .map_err(|e| err::Error::Other(e.to_string()))?;
+    }
+    log::info!("link {slug} of {}", record.owner);
+    Ok((ds, record.name))
+}
+
+#[derive(Deserialize)]
+pub struct ListRequest {
+    offset: Option<usize>,
+    limit: Option<usize>,
+    /// One of `created_at`, `name` and `length`.
+    sort: Option<String>,
+    /// `asc` or `desc`.
+    order: Option<String>,
+    /// List files shared with the caller instead of its own.
+    shared: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct FileInfo {
+    pub owner: String,
+    pub md5: String,
+    /// `None` for files stored before sha256 addressing.
+    pub sha256: Option<String>,
+    pub length: u64,
+    pub name: String,
+    pub mime: String,
+    pub created_at: u64,
+    /// `complete` or `uploading`.
+    pub state: String,
+    /// Number of bytes received.
+    pub committed: u64,
+}
+
+impl From<FileRecord> for FileInfo {
+    fn from(record: FileRecord) -> Self {
+        Self {
+            sha256: if record.sha256.is_empty() {
+                None
+            } else {
+                Some(record.sha256)
+            },
+            owner: record.owner,
+            md5: record.md5,
+            length: record.length,
+            committed: record.length,
+            name: record.name,
+            mime: record.mime,
+            created_at: record.created_at,
+            state: format!("complete"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct FileList {
+    pub total: usize,
+    pub file_v: Vec<FileInfo>,
+}
+
+/// Keep the file records in `file_v` on which the caller has `right`, its own records first.
+///
+/// Owners have every right on their files, admins may read any file, and others need a grant.
+async fn retain_accessible(
+    dm: Arc<dyn AsDataManager>,
+    auth: &crypto::User,
+    right: Right,
+    mut file_v: Vec<(String, FileRecord)>,
+) -> err::Result<Vec<(String, FileRecord)>> {
+    if file_v.iter().any(|(_, record)| record.owner != auth.email)
+        && !(right == Right::Read && auth.has(Scope::Admin))
+    {
+        let group_v = acl::groups_of(dm.clone(), &auth.email)
+            .await
+            .map_err(|e| err::Error::Other(e.to_string()))?;
+        let mut key_v: Vec<&str> = file_v
+            .iter()
+            .filter(|(_, record)| record.owner != auth.email)
+            .map(|(_, record)| record.key())
+            .collect();
+        key_v.sort();
+        key_v.dedup();
+        let mut grant_v = Vec::new();
+        for key in key_v {
+            grant_v.extend(
+                acl::list_by_key(dm.clone(), key)
+                    .await
+                    .map_err(|e| err::Error::Other(e.to_string()))?,
+            );
+        }
+        file_v.retain(|(_, record)| {
+            record.owner == auth.email
+                || grant_v.iter().any(|(_, grant)| {
+                    grant.owner == record.owner
+                        && grant.key == record.key()
+                        && grant.allows(&auth.email, &group_v, right)
+                })
+        });
+    }
+    file_v.sort_by_key(|(_, record)| record.owner != auth.email);
+    Ok(file_v)
+}
+
+/// Get the records of the file with md5 or sha256 `hash` on which the caller has `right`.
+async fn find_files(
+    dm: Arc<dyn AsDataManager>,
+    auth: &crypto::User,
+    hash: &str,
+    right: Right,
+) -> err::Result<Vec<(String, FileRecord)>> {
+    let file_v = file::find(dm.clone(), hash)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    retain_accessible(dm, auth, right, file_v).await
+}
+
+/// Get the records of other users that the caller may read.
+///
+/// Admins may read every file, others the files granted to them or to their groups.
+async fn list_shared_files(
+    dm: Arc<dyn AsDataManager>,
+    auth: &crypto::User,
+) -> err::Result<Vec<(String, FileRecord)>> {
+    if auth.has(Scope::Admin) {
+        return Ok(file::list(dm)
+            .await
+            .map_err(|e| err::Error::Other(e.to_string()))?
+            .into_iter()
+            .filter(|(_, record)| record.owner != auth.email)
+            .collect());
+    }
+    let mut file_v: Vec<(String, FileRecord)> = Vec::new();
+    for (_, grant) in acl::list_for(dm.clone(), &auth.email)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+    {
+        if !grant.right_v.contains(&Right::Read) {
+            continue;
+        }
+        for (node, record) in file::find(dm.clone(), &grant.key)
+            .await
+            .map_err(|e| err::Error::Other(e.to_string()))?
+        {
+            if record.owner == grant.owner
+                && record.key() == grant.key
+                && !file_v.iter().any(|(n, _)| *n == node)
+            {
+                file_v.push((node, record));
+            }
+        }
+    }
+    Ok(file_v)
+}
+
+pub async fn list_files(
+    dm: Arc<dyn AsDataManager>,
+    auth: &crypto::User,
+    lr: ListRequest,
+) -> err::Result<FileList> {
+    auth.require(Scope::Download)?;
+    let is_shared = lr.shared.unwrap_or(false);
+    let mut record_v: Vec<FileRecord> = if is_shared {
+        list_shared_files(dm, auth).await?
+    } else {
+        file::list_by_owner(dm, &auth.email)
+            .await
+            .map_err(|e| err::Error::Other(e.to_string()))?
+    }
+    .into_iter()
+    .map(|(_, record)| record)
+    .collect();
+    match lr.sort.as_deref().unwrap_or("created_at") {
+        "created_at" => record_v.sort_by_key(|record| record.created_at),
+        "name" => record_v.sort_by(|a, b| a.name.cmp(&b.name)),
+        "length" => record_v.sort_by_key(|record| record.length),
+        sort => return Err(err::Error::BadRequest(format!("invalid sort: {sort}"))),
+    }
+    match lr.order.as_deref().unwrap_or("asc") {
+        "asc" => (),
+        "desc" => record_v.reverse(),
+        order => return Err(err::Error::BadRequest(format!("invalid order: {order}"))),
+    }
+
+    let total = record_v.len();
+    let limit = std::cmp::min(lr.limit.unwrap_or(DEFAULT_PAGE_SIZE), MAX_PAGE_SIZE);
+    let file_v = record_v
+        .into_iter()
+        .skip(lr.offset.unwrap_or(0))
+        .take(limit)
+        .map(FileInfo::from)
+        .collect();
+    Ok(FileList { total, file_v })
+}
+
+/// Get the file with md5 or sha256 `hash` that the caller may read, or its pending upload.
+pub async fn stat_file(
+    dm: Arc<dyn AsDataManager>,
+    sessions: Arc<SessionStore>,
+    auth: &crypto::User,
+    hash: String,
+) -> err::Result<FileInfo> {
+    auth.require(Scope::Download)?;
+    let hash = hash.to_lowercase();
+    if let Some((_, record)) = find_files(dm, auth, &hash, Right::Read)
+        .await?
+        .into_iter()
+        .next()
+    {
+        return Ok(FileInfo::from(record));
+    }
+    let session = match get_own_session(&sessions, &hash, &auth.email).await {
+        Err(err::Error::NotFound(_)) => {
+            get_own_session(&sessions, &md5_session_id(&auth.email, &hash), &auth.email).await?
+        }
+        r => r?,
+    };
+    let record = FileRecord::new(
+        &session.md5,
+        "",
+        session.length,
+        &session.name,
+        &session.owner,
+    );
+    Ok(FileInfo {
+        committed: session.committed(),
+        created_at: session.created_at,
+        state: format!("uploading"),
+        ..FileInfo::from(record)
+    })
+}
+
+/// Delete the caller's records of the file with md5 or sha256 `hash`, or if it has none, the
+/// records it may delete through a grant. Grants go with the last record of their file, and
+/// each blob with the last record that refers to it.
+pub async fn delete_file(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    store: Arc<dyn AsBlobStore>,
+    auth: &crypto::User,
+    hash: String,
+) -> err::Result<String> {
+    log::info!("email: {}", auth.email);
+    auth.require(Scope::Delete)?;
+
+    let hash = hash.to_lowercase();
+    let mut file_v = find_files(dm.clone(), auth, &hash, Right::Delete).await?;
+    if file_v.is_empty() {
+        return Err(err::Error::NotFound(format!("no file {hash}")));
+    }
+    if file_v[0].1.owner == auth.email {
+        file_v.retain(|(_, record)| record.owner == auth.email);
+    }
+    for (node, _) in &file_v {
+        file::remove(dm.clone(), journal, node)
+            .await
+            .map_err(|e| err::Error::Other(e.to_string()))?;
+    }
+    for (_, record) in &file_v {
+        let key = record.key();
+        let record_v = file::find(dm.clone(), key)
+            .await
+            .map_err(|e| err::Error::Other(e.to_string()))?;
+        if !record_v
+            .iter()
+            .any(|(_, r)| r.key() == key && r.owner == record.owner)
+        {
+            acl::revoke(dm.clone(), journal, &record.owner, key, None)
+                .await
+                .map_err(|e| err::Error::Other(e.to_string()))?;
+        }
+        if record_v.iter().any(|(_, record)| record.key() == key) {
+            continue;
+        }
+        store
+            .delete(key)
+            .await
+            .map_err(|e| err::Error::Other(e.to_string()))?;
+    }
+    Ok(format!("success"))
+}
+
+/// Attach the existing blob of `sr.md5`, and `sr.sha256` if given, to the caller as a new file,
+/// without any transfer.
+///
+/// Blobs are only stored after their hash has been verified, so a blob with the declared md5
+/// and length has the declared content. Digests are no secret though, so only blobs the caller
+/// may already read are attached; any other blob has to be uploaded.
+pub async fn instant_upload(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    store: Arc<dyn AsBlobStore>,
+    auth: &crypto::User,
+    sr: SessionRequest,
+) -> err::Result<FileInfo> {
+    log::info!("email: {}", auth.email);
+    auth.require(Scope::Upload)?;
+
+    let (md5, sha256) = parse_digests(Some(sr.md5), sr.sha256)?;
+    let md5 = md5.unwrap_or_default();
+    let key = find_key(dm.clone(), store.as_ref(), Some(&md5), sha256.as_deref())
+        .await?
+        .ok_or(err::Error::NotFound(format!("no blob {md5}")))?;
+    if find_files(dm.clone(), auth, &key, Right::Read)
+        .await?
+        .is_empty()
+    {
+        return Err(err::Error::NotFound(format!("no blob {md5}")));
+    }
+    match store
+        .stat(&key)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+    {
+        Some(length) if length == sr.length => (),
+        _ => return Err(err::Error::NotFound(format!("no blob {md5}"))),
+    }
+    let sha256 = if key == md5 { "" } else { &key };
+    let record = FileRecord::new(&md5, sha256, sr.length, &sr.name, &auth.email);
+    record
+        .insert(dm, journal)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    Ok(FileInfo::from(record))
+}
+
+#[derive(Deserialize)]
+pub struct RenameRequest {
+    pub name: String,
+}
+
+/// Rename the file with md5 or sha256 `hash`, which the caller may write.
+pub async fn rename_file(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    auth: &crypto::User,
+    hash: String,
+    rr: RenameRequest,
+) -> err::Result<FileInfo> {
+    log::info!("email: {}", auth.email);
+    auth.require(Scope::Upload)?;
+
+    if rr.name.is_empty() {
+        return Err(err::Error::BadRequest(format!("empty name")));
+    }
+    let hash = hash.to_lowercase();
+    let (node, _) = find_files(dm.clone(), auth, &hash, Right::Write)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or(err::Error::NotFound(format!("no file {hash}")))?;
+    file::rename(dm.clone(), journal, &node, &rr.name)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    let record = FileRecord::load(dm.as_ref(), &node)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    Ok(FileInfo::from(record))
+}
+
+#[derive(Deserialize)]
+pub struct ShareRequest {
+    /// An email or `group:{name}`.
+    pub grantee: String,
+    pub right_v: Vec<Right>,
+}
+
+#[derive(Serialize)]
+pub struct GrantInfo {
+    pub grantee: String,
+    pub right_v: Vec<Right>,
+    pub created_at: u64,
+}
+
+/// Get the key of the caller's own file with md5 or sha256 `hash`.
+async fn get_own_key(
+    dm: Arc<dyn AsDataManager>,
+    auth: &crypto::User,
+    hash: &str,
+) -> err::Result<String> {
+    let hash = hash.to_lowercase();
+    file::find(dm, &hash)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+        .into_iter()
+        .find(|(_, record)| record.owner == auth.email)
+        .map(|(_, record)| record.key().to_string())
+        .ok_or(err::Error::NotFound(format!("no file {hash}")))
+}
+
+/// Share the caller's file with md5 or sha256 `hash`, replacing the former rights of
+/// `sr.grantee`.
+pub async fn share_file(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    auth: &crypto::User,
+    hash: String,
+    sr: ShareRequest,
+) -> err::Result<GrantInfo> {
+    log::info!("email: {}", auth.email);
+    auth.require(Scope::Upload)?;
+
+    if !acl::is_grantee(&sr.grantee) || sr.grantee == auth.email {
+        return Err(err::Error::BadRequest(format!(
+            "invalid grantee: {}",
+            sr.grantee
+        )));
+    }
+    if sr.right_v.is_empty() {
+        return Err(err::Error::BadRequest(format!("no rights")));
+    }
+    let key = get_own_key(dm.clone(), auth, &hash).await?;
+    let grant = acl::grant(dm, journal, &auth.email, &key, &sr.grantee, sr.right_v)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    Ok(GrantInfo {
+        grantee: grant.grantee,
+        right_v: grant.right_v,
+        created_at: grant.created_at,
+    })
+}
+
+pub async fn list_file_grants(
+    dm: Arc<dyn AsDataManager>,
+    auth: &crypto::User,
+    hash: String,
+) -> err::Result<Vec<GrantInfo>> {
+    let key = get_own_key(dm.clone(), auth, &hash).await?;
+    Ok(acl::list_by_key(dm, &key)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+        .into_iter()
+        .filter(|(_, grant)| grant.owner == auth.email)
+        .map(|(_, grant)| GrantInfo {
+            grantee: grant.grantee,
+            right_v: grant.right_v,
+            created_at: grant.created_at,
+        })
+        .collect())
+}
+
+/// Stop sharing the caller's file with md5 or sha256 `hash` with `grantee`.
+pub async fn unshare_file(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    auth: &crypto::User,
+    hash: String,
+    grantee: String,
+) -> err::Result<String> {
+    log::info!("email: {}", auth.email);
+
+    let key = get_own_key(dm.clone(), auth, &hash).await?;
+    if acl::revoke(dm, journal, &auth.email, &key, Some(&grantee))
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+        == 0
+    {
+        return Err(err::Error::NotFound(format!("no grant to {grantee}")));
+    }
+    Ok(format!("success"))
+}
+
+#[derive(Serialize)]
+pub struct ApiKeyInfo {
+    pub id: String,
+    /// Only present when the key is created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    pub created_at: u64,
+}
+
+pub async fn create_api_key(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    auth: &crypto::User,
+) -> err::Result<ApiKeyInfo> {
+    log::info!("email: {}", auth.email);
+
+    let (record, key) = ApiKeyRecord::generate(&auth.email);
+    record
+        .insert(dm, journal)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    Ok(ApiKeyInfo {
+        id: record.id,
+        key: Some(key),
+        created_at: record.created_at,
+    })
+}
+
+pub async fn list_api_keys(
+    dm: Arc<dyn AsDataManager>,
+    auth: &crypto::User,
+) -> err::Result<Vec<ApiKeyInfo>> {
+    Ok(api_key::list_by_owner(dm, &auth.email)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+        .into_iter()
+        .map(|(_, record)| ApiKeyInfo {
+            id: record.id,
+            key: None,
+            created_at: record.created_at,
+        })
+        .collect())
+}
+
+pub async fn delete_api_key(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    auth: &crypto::User,
+    id: String,
+) -> err::Result<String> {
+    log::info!("email: {}", auth.email);
+
+    let node = api_key::find(dm.clone(), &id)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+        .filter(|(_, record)| record.owner == auth.email)
+        .map(|(node, _)| node)
+        .ok_or(err::Error::NotFound(format!("no api key {id}")))?;
+    api_key::remove(dm, journal, &node)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    Ok(format!("success"))
+}
+
+#[derive(Serialize)]
+pub struct RoleInfo {
+    pub email: String,
+    pub role: Role,
+}
+
+#[derive(Deserialize)]
+pub struct RoleRequest {
+    pub role: Role,
+}
+
+pub async fn list_roles(
+    dm: Arc<dyn AsDataManager>,
+    auth: &crypto::User,
+) -> err::Result<Vec<RoleInfo>> {
+    auth.require(Scope::Admin)?;
+    Ok(role::list(dm)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+        .into_iter()
+        .map(|(_, email, role)| RoleInfo { email, role })
+        .collect())
+}
+
+/// Assign `rr.role` to `email`. It takes effect with the next token of `email`.
+pub async fn set_role(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    auth: &crypto::User,
+    email: String,
+    rr: RoleRequest,
+) -> err::Result<RoleInfo> {
+    log::info!("email: {}", auth.email);
+    auth.require(Scope::Admin)?;
+
+    if email == auth.email && rr.role != Role::Admin {
+        return Err(err::Error::Forbidden(format!("can not demote yourself")));
+    }
+    role::assign(dm, journal, &email, rr.role)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    log::info!("assigned {} to {email}", rr.role);
+    Ok(RoleInfo {
+        email,
+        role: rr.role,
+    })
+}
+
+/// Remove the role of `email`, so that it falls back to the default role.
+pub async fn delete_role(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    auth: &crypto::User,
+    email: String,
+) -> err::Result<String> {
+    log::info!("email: {}", auth.email);
+    auth.require(Scope::Admin)?;
+
+    if email == auth.email {
+        return Err(err::Error::Forbidden(format!("can not demote yourself")));
+    }
+    if !role::unassign(dm, journal, &email)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+    {
+        return Err(err::Error::NotFound(format!("no role of {email}")));
+    }
+    Ok(format!("success"))
+}
+
+/// End every session of `email` and delete its API keys.
+///
+/// Access tokens already issued are revoked until they expire.
+pub async fn revoke_user(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    auth: &crypto::User,
+    email: String,
+) -> err::Result<String> {
+    log::info!("email: {}", auth.email);
+    auth.require(Scope::Admin)?;
+
+    for (node, _) in refresh_token::list_by_owner(dm.clone(), &email)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+    {
+        refresh_token::remove(dm.clone(), journal, &node)
+            .await
+            .map_err(|e| err::Error::Other(e.to_string()))?;
+    }
+    for (node, _) in api_key::list_by_owner(dm.clone(), &email)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+    {
+        api_key::remove(dm.clone(), journal, &node)
+            .await
+            .map_err(|e| err::Error::Other(e.to_string()))?;
+    }
+    let option = get_token_option(dm.clone()).await?;
+    revocation::revoke_subject(
+        dm,
+        journal,
+        &email,
+        util::timestamp() + option.ttl + option.leeway,
+    )
+    .await
+    .map_err(|e| err::Error::Other(e.to_string()))?;
+    log::info!("revoked {email}");
+    Ok(format!("success"))
+}
+
+pub async fn list_group_members(
+    dm: Arc<dyn AsDataManager>,
+    auth: &crypto::User,
+    group: String,
+) -> err::Result<Vec<String>> {
+    auth.require(Scope::Admin)?;
+    acl::list_members(dm, &group)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))
+}
+
+pub async fn add_group_member(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    auth: &crypto::User,
+    group: String,
+    email: String,
+) -> err::Result<String> {
+    log::info!("email: {}", auth.email);
+    auth.require(Scope::Admin)?;
+
+    acl::add_member(dm, journal, &group, &email)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?;
+    Ok(format!("success"))
+}
+
+pub async fn remove_group_member(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    auth: &crypto::User,
+    group: String,
+    email: String,
+) -> err::Result<String> {
+    log::info!("email: {}", auth.email);
+    auth.require(Scope::Admin)?;
+
+    if !acl::remove_member(dm, journal, &group, &email)
+        .await
+        .map_err(|e| err::Error::Other(e.to_string()))?
+    {
+        return Err(err::Error::NotFound(format!("{email} is not in {group}")));
+    }
+    Ok(format!("success"))
+}
+
+#[cfg(test)]
+mod tests {
+    use edge_lib::{
+        data::{AsDataManager, MemDataManager},
+        Path,
+    };
+
+    use sha2::{Digest, Sha256};
+
+    use std::sync::Arc;
+
+    use crate::{
+        acl::{self, Right},
+        drop_box::{self, DropBoxRecord},
+        err,
+        file::{self, FileRecord},
+        graph::{self, Journal},
+        keyring::KeyStore,
+        refresh_token, revocation,
+        role::Role,
+        session::SessionStore,
+        share_link::ShareLinkRecord,
+        storage::{AsBlobStore, MemBlobStore},
+        util,
+    };
+
+    use axum::http::{header, HeaderMap, Method};
+
+    use super::{
+        add_link_failure, check_link_failures, commit_range, crypto, download_link,
+        download_presigned, finalize, get_content_range, get_cookie, instant_upload,
+        is_md5_uploaded, login, md5_session_id, open_md5_session, parse_content_range, parse_range,
+        presign, refresh, register, upload, upload_drop_box, DataSlice, FileRequest,
+        PresignRequest, RefreshRequest, SessionRequest, LINK_FAILURE_WINDOW, MAX_LINK_FAILURES,
+    };
+
+    #[test]
+    fn test_get_cookie() {
+        let mut hm = HeaderMap::new();
+        hm.insert(header::COOKIE, "a=1; token=x;b=\"2=3\"".parse().unwrap());
+        hm.append(header::COOKIE, "token=y; c".parse().unwrap());
+        let cookie = get_cookie(&hm).unwrap();
+        assert_eq!(cookie["a"], "1");
+        assert_eq!(cookie["token"], "x");
+        assert_eq!(cookie["b"], "2=3");
+        assert!(!cookie.contains_key("c"));
+        assert!(get_cookie(&HeaderMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-9", 100).unwrap(), Some((0, 10)));
+        assert_eq!(parse_range("bytes=90-200", 100).unwrap(), Some((90, 100)));
+        assert_eq!(parse_range("bytes=10-", 100).unwrap(), Some((10, 100)));
+        assert_eq!(parse_range("bytes=-10", 100).unwrap(), Some((90, 100)));
+        assert_eq!(parse_range("bytes=-200", 100).unwrap(), Some((0, 100)));
+        assert_eq!(parse_range("bytes=9-0", 100).unwrap(), None);
+        assert_eq!(parse_range("bytes=0-1,5-6", 100).unwrap(), None);
+        assert_eq!(parse_range("items=0-1", 100).unwrap(), None);
+        assert!(matches!(
+            parse_range("bytes=100-", 100),
+            Err(err::Error::RangeNotSatisfiable(100))
+        ));
+        assert!(matches!(
+            parse_range("bytes=-0", 100),
+            Err(err::Error::RangeNotSatisfiable(100))
+        ));
+        assert!(matches!(
+            parse_range("bytes=0-", 0),
+            Err(err::Error::RangeNotSatisfiable(0))
+        ));
+    }
+
+    #[test]
+    fn test_parse_content_range() {
+        assert_eq!(parse_content_range("bytes 0-9/10").unwrap(), (0, 10, 10));
+        assert_eq!(parse_content_range("bytes 5-5/100").unwrap(), (5, 6, 100));
+        assert!(parse_content_range("bytes 5-4/100").is_err());
+        assert!(parse_content_range("bytes 0-10/10").is_err());
+        assert!(parse_content_range("bytes */10").is_err());
+        assert!(parse_content_range("0-9/10").is_err());
+
+        assert!(matches!(
+            get_content_range(&HeaderMap::new()),
+            Err(err::Error::BadRequest(_))
+        ));
+        let mut hm = HeaderMap::new();
+        hm.insert(
+            header::CONTENT_RANGE,
+            header::HeaderValue::from_bytes(b"bytes 0-9/\xff").unwrap(),
+        );
+        assert!(matches!(
+            get_content_range(&hm),
+            Err(err::Error::BadRequest(_))
+        ));
+        hm.insert(header::CONTENT_RANGE, "bytes 0-9/10".parse().unwrap());
+        assert_eq!(get_content_range(&hm).unwrap(), (0, 10, 10));
+    }
+
+    #[tokio::test]
+    async fn test_finalize() {
+        let store = MemBlobStore::new();
+        let md5 = format!("{:x}", md5::compute(b"hello"));
+        let sha256 = format!("{:x}", Sha256::digest(b"hello"));
+
+        store.begin(&md5, 5).await.unwrap();
+        store.write_at(&md5, 0, b"hellO".to_vec()).await.unwrap();
+        assert!(matches!(
+            finalize(&store, &md5, &md5).await,
+            Err(err::Error::HashMismatch(_))
+        ));
+        assert_eq!(store.stat_temp(&md5).await.unwrap(), None);
+        assert_eq!(store.stat(&sha256).await.unwrap(), None);
+
+        store.begin(&md5, 5).await.unwrap();
+        store.write_at(&md5, 0, b"hello".to_vec()).await.unwrap();
+        let digest = finalize(&store, &md5, &md5).await.unwrap();
+        assert_eq!(digest.sha256, sha256);
+        assert_eq!(store.stat(&sha256).await.unwrap(), Some(5));
+
+        store.begin(&md5, 5).await.unwrap();
+        store.write_at(&md5, 0, b"hello".to_vec()).await.unwrap();
+        finalize(&store, &md5, &md5).await.unwrap();
+        assert_eq!(store.stat_temp(&md5).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_refresh() {
+        let dm = MemDataManager::new();
+        let journal = Journal::new();
+        let script = [
+            "root->token_ttl = = 60 _",
+            "root->token_leeway = = 0 _",
+            "root->refresh_ttl = = 60 _",
+            "root->token_issuer = = pool _",
+            "root->token_audience = = pool _",
+        ]
+        .join("\n");
+        graph::execute(dm.divide(), &journal, script, "test")
+            .await
+            .unwrap();
+        let keys = KeyStore::new(
+            std::env::temp_dir()
+                .join(format!("pool_test_refresh_{}", rand::random::<u64>()))
+                .join("keys.json"),
+            "secret",
+            "",
+        );
+        let auth = || crypto::Auth {
+            email: format!("a@b.c"),
+            password: format!("password"),
+        };
+        register(dm.divide(), &journal, auth()).await.unwrap();
+
+        let info = login(dm.divide(), &journal, &keys, auth()).await.unwrap();
+        let info = refresh(
+            dm.divide(),
+            &journal,
+            &keys,
+            RefreshRequest {
+                refresh_token: info.refresh_token,
+            },
+        )
+        .await
+        .unwrap();
+
+        // A newer `iat` does not get past the revocation, and the session ends.
+        revocation::revoke_subject(dm.divide(), &journal, "a@b.c", util::timestamp() + 60)
+            .await
+            .unwrap();
+        assert!(matches!(
+            refresh(
+                dm.divide(),
+                &journal,
+                &keys,
+                RefreshRequest {
+                    refresh_token: info.refresh_token,
+                },
+            )
+            .await,
+            Err(err::Error::NotLogin(_))
+        ));
+        assert!(refresh_token::list(dm.divide()).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_presign() {
+        let store = Arc::new(MemBlobStore::new());
+        let dm = MemDataManager::new();
+        let journal = Journal::new();
+        graph::execute(
+            dm.divide(),
+            &journal,
+            format!("root->path = = /pool _"),
+            "test",
+        )
+        .await
+        .unwrap();
+        let keys = KeyStore::new(
+            std::env::temp_dir()
+                .join(format!("pool_test_presign_{}", rand::random::<u64>()))
+                .join("keys.json"),
+            "ab",
+            "",
+        );
+        let md5 = format!("{:x}", md5::compute(b"hello"));
+        store.begin(&md5, 5).await.unwrap();
+        store.write_at(&md5, 0, b"hello".to_vec()).await.unwrap();
+        let digest = finalize(store.as_ref(), &md5, &md5).await.unwrap();
+        FileRecord::new(&md5, &digest.sha256, 5, "a.txt", "a@b.c")
+            .insert(dm.divide(), &journal)
+            .await
+            .unwrap();
+        acl::grant(
+            dm.divide(),
+            &journal,
+            "a@b.c",
+            &digest.sha256,
+            "b@b.c",
+            vec![Right::Read],
+        )
+        .await
+        .unwrap();
+
+        let user = crypto::User {
+            email: format!("b@b.c"),
+            session: None,
+            scope_v: Role::Reader.scope_v(),
+        };
+        let url = presign(
+            dm.divide(),
+            store.clone(),
+            &keys,
+            &user,
+            PresignRequest {
+                md5: None,
+                sha256: Some(digest.sha256.clone()),
+                expires_in: None,
+                start: None,
+                size: None,
+            },
+        )
+        .await
+        .unwrap()
+        .url;
+        let request = |url: &str| {
+            let uri: axum::http::Uri = url.parse().unwrap();
+            axum::extract::Query::<FileRequest>::try_from_uri(&uri)
+                .unwrap()
+                .0
+        };
+        let stream = download_presigned(
+            dm.divide(),
+            store.clone(),
+            &keys,
+            &HeaderMap::new(),
+            request(&url),
+        )
+        .await
+        .unwrap();
+        assert_eq!(stream.length, 5);
+
+        // The signer is signed.
+        let forged = url.replace("sub=b@b.c", "sub=a@b.c");
+        assert!(matches!(
+            download_presigned(
+                dm.divide(),
+                store.clone(),
+                &keys,
+                &HeaderMap::new(),
+                request(&forged)
+            )
+            .await,
+            Err(err::Error::NotLogin(_))
+        ));
+
+        // Unsharing the file ends the URL.
+        acl::revoke(
+            dm.divide(),
+            &journal,
+            "a@b.c",
+            &digest.sha256,
+            Some("b@b.c"),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            download_presigned(
+                dm.divide(),
+                store.clone(),
+                &keys,
+                &HeaderMap::new(),
+                request(&url)
+            )
+            .await,
+            Err(err::Error::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_link_failures() {
+        for _ in 0..MAX_LINK_FAILURES {
+            check_link_failures("s0", 1000).unwrap();
+            add_link_failure("s0", 1000);
+        }
+        assert!(matches!(
+            check_link_failures("s0", 1010),
+            Err(err::Error::TooManyRequests(50))
+        ));
+        check_link_failures("s1", 1010).unwrap();
+        check_link_failures("s0", 1000 + LINK_FAILURE_WINDOW).unwrap();
+
+        // A new window starts counting again.
+        add_link_failure("s0", 1000 + LINK_FAILURE_WINDOW);
+        check_link_failures("s0", 1000 + LINK_FAILURE_WINDOW).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_link() {
+        let store = Arc::new(MemBlobStore::new());
+        let dm = MemDataManager::new();
+        let journal = Journal::new();
+        let md5 = format!("{:x}", md5::compute(b"hello"));
+        store.begin(&md5, 5).await.unwrap();
+        store.write_at(&md5, 0, b"hello".to_vec()).await.unwrap();
+        let digest = finalize(store.as_ref(), &md5, &md5).await.unwrap();
+        let record = FileRecord::new(&md5, &digest.sha256, 5, "a.txt", "a@b.c");
+        record.insert(dm.divide(), &journal).await.unwrap();
+        let link = ShareLinkRecord::new("a@b.c", record.key(), "a.txt", "", 0, 1).unwrap();
+        link.insert(dm.divide(), &journal).await.unwrap();
+        let range = |range: &str| {
+            let mut hm = HeaderMap::new();
+            hm.insert(header::RANGE, range.parse().unwrap());
+            hm
+        };
+        let download = |method: Method, hm: HeaderMap| {
+            let dm = dm.divide();
+            let journal = &journal;
+            let store = store.clone();
+            let slug = link.slug.clone();
+            async move {
+                download_link(dm, journal, store, &method, &hm, slug, Default::default()).await
+            }
+        };
+
+        // HEAD requests are free, any other request uses up a download, whatever its range.
+        download(Method::HEAD, HeaderMap::new()).await.unwrap();
+        let (ds, _) = download(Method::GET, range("bytes=1-")).await.unwrap();
+        assert_eq!((ds.start, ds.end), (1, 5));
+        for (method, hm) in [
+            (Method::GET, range("bytes=0-")),
+            (Method::GET, range("bytes=1-")),
+            (Method::GET, HeaderMap::new()),
+            (Method::HEAD, HeaderMap::new()),
+        ] {
+            assert!(matches!(
+                download(method, hm).await,
+                Err(err::Error::NotFound(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_drop_box() {
+        let dir =
+            std::env::temp_dir().join(format!("pool_test_drop_box_{}", rand::random::<u64>()));
+        let sessions = Arc::new(SessionStore::new(&dir, 60));
+        let store = Arc::new(MemBlobStore::new());
+        let dm = MemDataManager::new();
+        let journal = Journal::new();
+        let record = DropBoxRecord::new("a@b.c", "reports", 0, 1, 0);
+        record.insert(dm.divide(), &journal).await.unwrap();
+        let slice = |content: &str, offset: usize, length: usize| DataSlice {
+            md5: format!("{:x}", md5::compute(content)),
+            offset: offset as u64,
+            slice_value: content.as_bytes()[offset..offset + length].to_vec(),
+            length: content.len() as u64,
+            name: format!("{content}.txt"),
+        };
+        let upload = |ds: DataSlice| {
+            upload_drop_box(
+                dm.divide(),
+                &journal,
+                store.clone(),
+                sessions.clone(),
+                record.slug.clone(),
+                ds,
+            )
+        };
+
+        assert!(matches!(
+            upload(DataSlice {
+                offset: u64::MAX,
+                ..slice("hello", 0, 2)
+            })
+            .await,
+            Err(err::Error::BadRequest(_))
+        ));
+
+        // A started file holds room in the box until it completes.
+        upload(slice("hello", 0, 2)).await.unwrap();
+        assert!(matches!(
+            upload(slice("world", 0, 5)).await,
+            Err(err::Error::Forbidden(_))
+        ));
+        upload(slice("hello", 2, 3)).await.unwrap();
+        let (_, found) = drop_box::find(dm.divide(), &record.slug)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.file_count, 1);
+        assert!(matches!(
+            upload(slice("again", 0, 5)).await,
+            Err(err::Error::Forbidden(_))
+        ));
+        assert_eq!(file::list(dm.divide()).await.unwrap().len(), 1);
+        assert!(store.list_temp().await.unwrap().is_empty());
+
+        // An abandoned file frees its room once its session is gone.
+        let record = DropBoxRecord::new("a@b.c", "drafts", 0, 1, 0);
+        record.insert(dm.divide(), &journal).await.unwrap();
+        let upload = |ds: DataSlice| {
+            upload_drop_box(
+                dm.divide(),
+                &journal,
+                store.clone(),
+                sessions.clone(),
+                record.slug.clone(),
+                ds,
+            )
+        };
+        upload(slice("hello", 0, 2)).await.unwrap();
+        let md5 = format!("{:x}", md5::compute("hello"));
+        sessions
+            .remove(&format!("{}{md5}", record.slug))
+            .await
+            .unwrap();
+        upload(slice("world", 0, 5)).await.unwrap();
+        assert_eq!(file::list(dm.divide()).await.unwrap().len(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_upload() {
+        let dir = std::env::temp_dir().join(format!("pool_test_upload_{}", rand::random::<u64>()));
+        let sessions = Arc::new(SessionStore::new(&dir, 60));
+        let store = Arc::new(MemBlobStore::new());
+        let dm = MemDataManager::new();
+        let journal = Journal::new();
+        let auth = crypto::User {
+            email: format!("a@b.c"),
+            session: None,
+            scope_v: Role::Writer.scope_v(),
+        };
+        let slice = |offset: u64| DataSlice {
+            md5: format!("{:x}", md5::compute(b"hello")),
+            offset,
+            slice_value: b"llo".to_vec(),
+            length: 5,
+            name: String::new(),
+        };
+
+        // An offset that wraps around is out of bound, not a write elsewhere.
+        for offset in [3, u64::MAX - 1] {
+            assert!(matches!(
+                upload(
+                    dm.divide(),
+                    &journal,
+                    store.clone(),
+                    sessions.clone(),
+                    &auth,
+                    slice(offset)
+                )
+                .await,
+                Err(err::Error::BadRequest(_))
+            ));
+        }
+        upload(
+            dm.divide(),
+            &journal,
+            store.clone(),
+            sessions.clone(),
+            &auth,
+            slice(2),
+        )
+        .await
+        .unwrap();
+        assert_eq!(store.list_temp().await.unwrap().len(), 1);
+
+        graph::execute(
+            dm.divide(),
+            &journal,
+            format!("root->max_upload_size = = 4 _"),
+            "test",
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            upload(
+                dm.divide(),
+                &journal,
+                store.clone(),
+                sessions.clone(),
+                &auth,
+                slice(2)
+            )
+            .await,
+            Err(err::Error::PayloadTooLarge(4))
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_instant_upload() {
+        let store = Arc::new(MemBlobStore::new());
+        let dm = MemDataManager::new();
+        let journal = Journal::new();
+        let md5 = format!("{:x}", md5::compute(b"hello"));
+        store.begin(&md5, 5).await.unwrap();
+        store.write_at(&md5, 0, b"hello".to_vec()).await.unwrap();
+        let digest = finalize(store.as_ref(), &md5, &md5).await.unwrap();
+        FileRecord::new(&md5, &digest.sha256, 5, "a.txt", "a@b.c")
+            .insert(dm.divide(), &journal)
+            .await
+            .unwrap();
+
+        let user = |email: &str| crypto::User {
+            email: email.to_string(),
+            session: None,
+            scope_v: Role::Writer.scope_v(),
+        };
+        let request = || SessionRequest {
+            md5: md5.clone(),
+            sha256: None,
+            length: 5,
+            name: format!("b.txt"),
+        };
+        // Knowing the digest is not enough to get the blob.
+        assert!(matches!(
+            instant_upload(
+                dm.divide(),
+                &journal,
+                store.clone(),
+                &user("b@b.c"),
+                request()
+            )
+            .await,
+            Err(err::Error::NotFound(_))
+        ));
+
+        acl::grant(
+            dm.divide(),
+            &journal,
+            "a@b.c",
+            &digest.sha256,
+            "b@b.c",
+            vec![Right::Read],
+        )
+        .await
+        .unwrap();
+        let info = instant_upload(
+            dm.divide(),
+            &journal,
+            store.clone(),
+            &user("b@b.c"),
+            request(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(info.sha256, Some(digest.sha256));
+        assert_eq!(file::list(dm.divide()).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_commit_range() {
+        let dir =
+            std::env::temp_dir().join(format!("pool_test_commit_range_{}", rand::random::<u64>()));
+        let sessions = SessionStore::new(&dir, 60);
+        let store = MemBlobStore::new();
+        let dm = MemDataManager::new();
+        let journal = Journal::new();
+        let md5 = format!("{:x}", md5::compute(b"hello world"));
+        let sha256 = format!("{:x}", Sha256::digest(b"hello world"));
+
+        // Chunks arrive out of order and one of them twice.
+        for (start, chunk) in [(6, "world"), (0, "hello"), (6, "world"), (5, " ")] {
+            let session = open_md5_session(&store, &sessions, &md5, 11, "email")
+                .await
+                .unwrap();
+            assert!(!session.is_complete());
+            let end = start + chunk.len() as u64;
+            store
+                .write_at(&session.id, start, chunk.as_bytes().to_vec())
+                .await
+                .unwrap();
+            commit_range(
+                dm.divide(),
+                &journal,
+                &store,
+                &sessions,
+                &session.id,
+                start,
+                end,
+            )
+            .await
+            .unwrap();
+        }
+        assert_eq!(store.stat(&sha256).await.unwrap(), Some(11));
+        let id = md5_session_id("email", &md5);
+        assert!(sessions.get(&id).await.unwrap().is_none());
+        // A chunk sent again after the upload finished opens no session.
+        assert!(is_md5_uploaded(dm.divide(), &sessions, &md5, 11, "email")
+            .await
+            .unwrap());
+
+        // Another user uploading the same content gets a session of its own.
+        let session = open_md5_session(&store, &sessions, &md5, 11, "other")
+            .await
+            .unwrap();
+        assert_ne!(session.id, id);
+        assert_eq!(session.owner, "other");
+        assert!(!is_md5_uploaded(dm.divide(), &sessions, &md5, 11, "other")
+            .await
+            .unwrap());
+        let rs = dm.get(&Path::from_str("root->file->md5")).await.unwrap();
+        assert_eq!(rs, vec![md5.clone()]);
+        let rs = dm.get(&Path::from_str("root->file->sha256")).await.unwrap();
+        assert_eq!(rs, vec![sha256.clone()]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,319 @@
+//! Upload sessions.
+//!
+//! A session is declared before any bytes are sent and is identified by a server-issued id,
+//! which also names its temp blob. Sessions are persisted as json files, so that they survive a
+//! restart, and expire when idle for longer than a ttl.
+//!
+//! Chunks may arrive in any order and more than once, so a session tracks the set of received
+//! byte ranges rather than a single offset.
+use std::{io, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::util;
+
+// Public
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadSession {
+    pub id: String,
+    pub md5: String,
+    pub length: u64,
+    pub name: String,
+    pub owner: String,
+    pub created_at: u64,
+    pub updated_at: u64,
+    /// Committed byte ranges as sorted, disjoint, non-adjacent `[start, end)` pairs.
+    #[serde(default)]
+    pub range_v: Vec<(u64, u64)>,
+    /// Set by the request that is finalizing the session.
+    #[serde(default)]
+    pub is_finalizing: bool,
+    /// Slug of the drop box the file is uploaded through, empty otherwise.
+    #[serde(default)]
+    pub drop_box: String,
+}
+
+impl UploadSession {
+    /// Mark `start..end` as committed.
+    pub fn commit(&mut self, start: u64, end: u64) {
+        if start >= end {
+            return;
+        }
+        let mut start = start;
+        let mut end = end;
+        let mut range_v = Vec::with_capacity(self.range_v.len() + 1);
+        for &(s, e) in &self.range_v {
+            if e < start || s > end {
+                range_v.push((s, e));
+            } else {
+                start = std::cmp::min(start, s);
+                end = std::cmp::max(end, e);
+            }
+        }
+        let i = range_v.partition_point(|&(s, _)| s < start);
+        range_v.insert(i, (start, end));
+        self.range_v = range_v;
+    }
+
+    /// Number of committed bytes.
+    pub fn committed(&self) -> u64 {
+        self.range_v.iter().map(|(s, e)| e - s).sum()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.committed() == self.length
+    }
+}
+
+pub struct SessionStore {
+    dir: PathBuf,
+    ttl: u64,
+    lock: Mutex<()>,
+}
+
+impl SessionStore {
+    /// Sessions are persisted under `dir` and expire after `ttl` idle seconds.
+    pub fn new(dir: impl Into<PathBuf>, ttl: u64) -> Self {
+        Self {
+            dir: dir.into(),
+            ttl,
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn ttl(&self) -> u64 {
+        self.ttl
+    }
+
+    pub fn is_expired(&self, session: &UploadSession, now: u64) -> bool {
+        now.saturating_sub(session.updated_at) > self.ttl
+    }
+
+    pub async fn create(
+        &self,
+        md5: &str,
+        length: u64,
+        name: &str,
+        owner: &str,
+    ) -> io::Result<UploadSession> {
+        let now = util::timestamp();
+        let session = UploadSession {
+            id: util::byte_v2hex(&rand::random::<[u8; 24]>()),
+            md5: md5.to_string(),
+            length,
+            name: name.to_string(),
+            owner: owner.to_string(),
+            created_at: now,
+            updated_at: now,
+            range_v: Vec::new(),
+            is_finalizing: false,
+            drop_box: String::new(),
+        };
+        let _guard = self.lock.lock().await;
+        self.save(&session).await?;
+        Ok(session)
+    }
+
+    /// Get session `id`, or create it with the given fields if it does not exist.
+    ///
+    /// Returns whether the session was created.
+    pub async fn get_or_create(
+        &self,
+        id: &str,
+        md5: &str,
+        length: u64,
+        name: &str,
+        owner: &str,
+    ) -> io::Result<(UploadSession, bool)> {
+        let _guard = self.lock.lock().await;
+        if let Some(session) = self.load(id).await? {
+            return Ok((session, false));
+        }
+        let now = util::timestamp();
+        let session = UploadSession {
+            id: id.to_string(),
+            md5: md5.to_string(),
+            length,
+            name: name.to_string(),
+            owner: owner.to_string(),
+            created_at: now,
+            updated_at: now,
+            range_v: Vec::new(),
+            is_finalizing: false,
+            drop_box: String::new(),
+        };
+        self.save(&session).await?;
+        Ok((session, true))
+    }
+
+    /// Get session `id` unless it does not exist or has expired.
+    pub async fn get(&self, id: &str) -> io::Result<Option<UploadSession>> {
+        let _guard = self.lock.lock().await;
+        self.load(id).await
+    }
+
+    /// Apply `f` to session `id` and persist the result.
+    ///
+    /// Returns `None` if the session does not exist or has expired.
+    pub async fn update<F>(&self, id: &str, f: F) -> io::Result<Option<UploadSession>>
+    where
+        F: FnOnce(&mut UploadSession),
+    {
+        let _guard = self.lock.lock().await;
+        let mut session = match self.load(id).await? {
+            Some(session) => session,
+            None => return Ok(None),
+        };
+        f(&mut session);
+        session.updated_at = util::timestamp();
+        self.save(&session).await?;
+        Ok(Some(session))
+    }
+
+    /// List sessions that have been idle for longer than the ttl at `now`.
+    pub async fn list_expired(&self, now: u64) -> io::Result<Vec<UploadSession>> {
+        let _guard = self.lock.lock().await;
+        let mut session_v = self.load_all().await?;
+        session_v.retain(|session| self.is_expired(session, now));
+        Ok(session_v)
+    }
+
+    /// List the live sessions of drop box `drop_box` at `now`.
+    pub async fn list_by_drop_box(
+        &self,
+        drop_box: &str,
+        now: u64,
+    ) -> io::Result<Vec<UploadSession>> {
+        let _guard = self.lock.lock().await;
+        let mut session_v = self.load_all().await?;
+        session_v.retain(|session| session.drop_box == drop_box && !self.is_expired(session, now));
+        Ok(session_v)
+    }
+
+    pub async fn remove(&self, id: &str) -> io::Result<()> {
+        let _guard = self.lock.lock().await;
+        util::not_found_as_none(tokio::fs::remove_file(self.path(id)).await)?;
+        Ok(())
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}.json"))
+    }
+
+    async fn load(&self, id: &str) -> io::Result<Option<UploadSession>> {
+        if !id.chars().all(|ch| ch.is_ascii_alphanumeric()) {
+            return Ok(None);
+        }
+        let content = match util::not_found_as_none(tokio::fs::read(self.path(id)).await)? {
+            Some(content) => content,
+            None => return Ok(None),
+        };
+        let session: UploadSession = serde_json::from_slice(&content).map_err(io::Error::other)?;
+        if self.is_expired(&session, util::timestamp()) {
+            return Ok(None);
+        }
+        Ok(Some(session))
+    }
+
+    /// Load every session, expired or not, skipping unreadable files.
+    async fn load_all(&self) -> io::Result<Vec<UploadSession>> {
+        let mut session_v = Vec::new();
+        let mut entries = match util::not_found_as_none(tokio::fs::read_dir(&self.dir).await)? {
+            Some(entries) => entries,
+            None => return Ok(session_v),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|s| s.to_str()) != Some("json") {
+                continue;
+            }
+            let content = tokio::fs::read(&path).await?;
+            match serde_json::from_slice(&content) {
+                Ok(session) => session_v.push(session),
+                Err(e) => log::warn!("{e}\nwhen parse {}", path.display()),
+            }
+        }
+        Ok(session_v)
+    }
+
+    async fn save(&self, session: &UploadSession) -> io::Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let path = self.path(&session.id);
+        let temp_path = path.with_extension("json.temp");
+        tokio::fs::write(
+            &temp_path,
+            serde_json::to_vec(session).map_err(io::Error::other)?,
+        )
+        .await?;
+        tokio::fs::rename(&temp_path, &path).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SessionStore, UploadSession};
+
+    #[test]
+    fn test_commit() {
+        let mut session = UploadSession {
+            id: "id".to_string(),
+            md5: "md5".to_string(),
+            length: 10,
+            name: "a.txt".to_string(),
+            owner: "email".to_string(),
+            created_at: 0,
+            updated_at: 0,
+            range_v: Vec::new(),
+            is_finalizing: false,
+            drop_box: String::new(),
+        };
+        session.commit(6, 8);
+        session.commit(0, 2);
+        session.commit(3, 4);
+        assert_eq!(session.range_v, vec![(0, 2), (3, 4), (6, 8)]);
+        session.commit(0, 2);
+        assert_eq!(session.range_v, vec![(0, 2), (3, 4), (6, 8)]);
+        session.commit(2, 3);
+        assert_eq!(session.range_v, vec![(0, 4), (6, 8)]);
+        session.commit(5, 7);
+        assert_eq!(session.range_v, vec![(0, 4), (5, 8)]);
+        assert!(!session.is_complete());
+        session.commit(4, 10);
+        assert_eq!(session.range_v, vec![(0, 10)]);
+        assert!(session.is_complete());
+    }
+
+    #[tokio::test]
+    async fn test() {
+        let dir = std::env::temp_dir().join(format!("pool_test_session_{}", rand::random::<u64>()));
+        let store = SessionStore::new(&dir, 60);
+        let session = store.create("md5", 10, "a.txt", "email").await.unwrap();
+
+        // Sessions survive a restart.
+        let store = SessionStore::new(&dir, 60);
+        let session = store
+            .update(&session.id, |session| session.commit(4, 8))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(session.range_v, vec![(4, 8)]);
+        assert_eq!(
+            store.get(&session.id).await.unwrap().unwrap().committed(),
+            4
+        );
+        assert!(store.is_expired(&session, session.updated_at + 61));
+
+        let (_, is_created) = store
+            .get_or_create(&session.id, "md5", 10, "a.txt", "email")
+            .await
+            .unwrap();
+        assert!(!is_created);
+
+        store.remove(&session.id).await.unwrap();
+        assert!(store.get(&session.id).await.unwrap().is_none());
+        assert!(store.get("../x").await.unwrap().is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,267 @@
+//! Public share links in the edge graph.
+//!
+//! Every link is a `root->share_link` node, addressed by a short random slug. A link may require
+//! a password, stored like user passwords, expire, and allow a limited number of downloads.
+//! Links are indexed by slug and by owner.
+use std::{io, sync::Arc};
+
+use edge_lib::{data::AsDataManager, Path};
+
+use crate::{
+    graph::{self, execute, get_field, Journal},
+    user, util,
+};
+
+const SLUG_LENGTH: usize = 10;
+
+async fn load_all(
+    dm: Arc<dyn AsDataManager>,
+    node_v: Vec<String>,
+) -> io::Result<Vec<(String, ShareLinkRecord)>> {
+    let mut link_v = Vec::with_capacity(node_v.len());
+    for node in node_v {
+        let record = ShareLinkRecord::load(dm.as_ref(), &node).await?;
+        link_v.push((node, record));
+    }
+    Ok(link_v)
+}
+
+// Public
+#[derive(Debug, Clone)]
+pub struct ShareLinkRecord {
+    pub slug: String,
+    pub owner: String,
+    /// Blob key of the file, see `FileRecord::key`.
+    pub key: String,
+    pub name: String,
+    /// Argon2 PHC string, empty without a password.
+    pub password_hash: String,
+    /// 0 if the link never expires.
+    pub expires_at: u64,
+    /// 0 if downloads are unlimited.
+    pub max_downloads: u64,
+    pub download_count: u64,
+    pub created_at: u64,
+}
+
+impl ShareLinkRecord {
+    /// Record a link created now with a new slug, hashing `password` if it is not empty.
+    pub fn new(
+        owner: &str,
+        key: &str,
+        name: &str,
+        password: &str,
+        expires_at: u64,
+        max_downloads: u64,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            slug: util::slug(SLUG_LENGTH),
+            owner: owner.to_string(),
+            key: key.to_string(),
+            name: name.to_string(),
+            password_hash: if password.is_empty() {
+                String::new()
+            } else {
+                user::hash_password(password)?
+            },
+            expires_at,
+            max_downloads,
+            download_count: 0,
+            created_at: util::timestamp(),
+        })
+    }
+
+    /// Load the record of link node `node`.
+    pub async fn load(dm: &dyn AsDataManager, node: &str) -> io::Result<Self> {
+        let password_hash = util::hex2byte_v(&get_field(dm, node, "password_hash").await?);
+        Ok(Self {
+            slug: get_field(dm, node, "slug").await?,
+            owner: util::unescape(&get_field(dm, node, "owner").await?),
+            key: get_field(dm, node, "key").await?,
+            name: util::unescape(&get_field(dm, node, "name").await?),
+            password_hash: String::from_utf8(password_hash).map_err(io::Error::other)?,
+            expires_at: get_field(dm, node, "expires_at")
+                .await?
+                .parse()
+                .unwrap_or(0),
+            max_downloads: get_field(dm, node, "max_downloads")
+                .await?
+                .parse()
+                .unwrap_or(0),
+            download_count: get_field(dm, node, "download_count")
+                .await?
+                .parse()
+                .unwrap_or(0),
+            created_at: get_field(dm, node, "created_at")
+                .await?
+                .parse()
+                .unwrap_or(0),
+        })
+    }
+
+    /// Whether the link can still be used at `now`.
+    pub fn is_valid(&self, now: u64) -> bool {
+        (self.expires_at == 0 || now < self.expires_at)
+            && (self.max_downloads == 0 || self.download_count < self.max_downloads)
+    }
+
+    /// Verify `password` against the password of the link, if it has one.
+    pub fn verify(&self, password: &str) -> bool {
+        self.password_hash.is_empty() || user::verify_password(&self.password_hash, password)
+    }
+
+    /// Write this record into the graph as a new `root->share_link` node.
+    pub async fn insert(&self, dm: Arc<dyn AsDataManager>, journal: &Journal) -> io::Result<()> {
+        let script = self.line_v(&graph::new_node()).join("\n");
+        execute(dm, journal, script, "insert share_link").await
+    }
+
+    /// Script lines writing this record as `root->share_link` node `node`, with its indexes.
+    fn line_v(&self, node: &str) -> Vec<String> {
+        let mut line_v = vec![
+            format!("$->$share_link = = {node} _"),
+            format!("$->$share_link->slug = = {} _", self.slug),
+            format!("$->$share_link->owner = = {} _", util::escape(&self.owner)),
+            format!("$->$share_link->key = = {} _", self.key),
+            format!("$->$share_link->name = = {} _", util::escape(&self.name)),
+        ];
+        if !self.password_hash.is_empty() {
+            line_v.push(format!(
+                "$->$share_link->password_hash = = {} _",
+                util::byte_v2hex(self.password_hash.as_bytes())
+            ));
+        }
+        line_v.push(format!(
+            "$->$share_link->expires_at = = {} _",
+            self.expires_at
+        ));
+        line_v.push(format!(
+            "$->$share_link->max_downloads = = {} _",
+            self.max_downloads
+        ));
+        line_v.push(format!(
+            "$->$share_link->download_count = = {} _",
+            self.download_count
+        ));
+        line_v.push(format!(
+            "$->$share_link->created_at = = {} _",
+            self.created_at
+        ));
+        line_v.push(format!("root->share_link += = $->$share_link _"));
+        line_v.extend(graph::index_line_v(
+            "share_link_by_slug",
+            &self.slug,
+            "share_link",
+        ));
+        line_v.extend(graph::index_line_v(
+            "share_link_by_owner",
+            &self.owner,
+            "share_link",
+        ));
+        line_v
+    }
+}
+
+/// List all link records with their nodes.
+pub async fn list(dm: Arc<dyn AsDataManager>) -> io::Result<Vec<(String, ShareLinkRecord)>> {
+    let node_v = dm.get(&Path::from_str("root->share_link")).await?;
+    load_all(dm, node_v).await
+}
+
+/// List the link records of `owner` with their nodes.
+pub async fn list_by_owner(
+    dm: Arc<dyn AsDataManager>,
+    owner: &str,
+) -> io::Result<Vec<(String, ShareLinkRecord)>> {
+    let node_v = graph::lookup(dm.as_ref(), "share_link_by_owner", owner).await?;
+    load_all(dm, node_v).await
+}
+
+/// Find the link with `slug`, with its node.
+pub async fn find(
+    dm: Arc<dyn AsDataManager>,
+    slug: &str,
+) -> io::Result<Option<(String, ShareLinkRecord)>> {
+    let node_v = graph::lookup(dm.as_ref(), "share_link_by_slug", slug).await?;
+    Ok(load_all(dm, node_v).await?.into_iter().next())
+}
+
+/// Set the download count of link node `node`.
+pub async fn set_download_count(
+    dm: Arc<dyn AsDataManager>,
+    journal: &Journal,
+    node: &str,
+    download_count: u64,
+) -> io::Result<()> {
+    let script = [
+        format!("$->$share_link = = {node} _"),
+        format!("$->$share_link->download_count = = {download_count} _"),
+    ]
+    .join("\n");
+    execute(dm, journal, script, "count share_link").await
+}
+
+/// Remove link node `node` from `root->share_link` and its indexes.
+pub async fn remove(dm: Arc<dyn AsDataManager>, journal: &Journal, node: &str) -> io::Result<()> {
+    let record = ShareLinkRecord::load(dm.as_ref(), node).await?;
+    let index_v = [
+        ("share_link_by_slug", record.slug.as_str()),
+        ("share_link_by_owner", record.owner.as_str()),
+    ];
+    graph::remove_node(dm, journal, "share_link", node, &index_v).await
+}
+
+/// Scripts recreating every link record on its node.
+pub async fn snapshot(dm: Arc<dyn AsDataManager>) -> io::Result<Vec<String>> {
+    Ok(list(dm)
+        .await?
+        .into_iter()
+        .map(|(node, record)| record.line_v(&node).join("\n"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use edge_lib::data::{AsDataManager, MemDataManager};
+
+    use crate::graph::Journal;
+
+    use super::{find, remove, set_download_count, ShareLinkRecord};
+
+    #[test]
+    fn test_is_valid() {
+        let mut record = ShareLinkRecord::new("a@b.c", "key", "a.txt", "", 100, 2).unwrap();
+        assert_eq!(record.slug.len(), 10);
+        assert!(record.verify(""));
+        assert!(record.is_valid(99));
+        assert!(!record.is_valid(100));
+        record.download_count = 2;
+        assert!(!record.is_valid(99));
+
+        let record = ShareLinkRecord::new("a@b.c", "key", "a.txt", "password", 0, 0).unwrap();
+        assert!(record.verify("password"));
+        assert!(!record.verify(""));
+        assert!(record.is_valid(u64::MAX));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test() {
+        let dm = MemDataManager::new();
+        let journal = Journal::new();
+        let record = ShareLinkRecord::new("a@b.c", "key", "a b.txt", "password", 0, 1).unwrap();
+        record.insert(dm.divide(), &journal).await.unwrap();
+
+        let (node, found) = find(dm.divide(), &record.slug).await.unwrap().unwrap();
+        assert_eq!(found.name, "a b.txt");
+        assert!(found.verify("password"));
+
+        set_download_count(dm.divide(), &journal, &node, 1)
+            .await
+            .unwrap();
+        let (node, found) = find(dm.divide(), &record.slug).await.unwrap().unwrap();
+        assert!(!found.is_valid(0));
+
+        remove(dm.divide(), &journal, &node).await.unwrap();
+        assert!(find(dm.divide(), &record.slug).await.unwrap().is_none());
+    }
+}
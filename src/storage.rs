@@ -0,0 +1,72 @@
+//! Blob storage shared by the http server and tools.
+//!
+//! An upload is written into a temp blob identified by an upload id, then finalized into a
+//! content-addressed blob identified by its key, the sha256 of its content. Blobs stored before
+//! sha256 addressing keep their md5 as key.
+mod fs;
+mod mem;
+mod s3;
+
+use std::{future::Future, io, pin::Pin};
+
+use tokio::io::AsyncRead;
+
+pub use fs::{migrate, FsBlobStore};
+pub use mem::MemBlobStore;
+pub use s3::{S3BlobStore, S3Option};
+
+pub type BlobFuture<T> = Pin<Box<dyn Future<Output = io::Result<T>> + Send>>;
+
+pub type BlobReader = Pin<Box<dyn AsyncRead + Send>>;
+
+#[derive(Debug, Clone)]
+pub struct TempInfo {
+    pub id: String,
+    pub length: u64,
+    /// Seconds since the unix epoch.
+    pub modified_at: u64,
+}
+
+/// Digests of a blob as lowercase hex.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Digest {
+    pub md5: String,
+    pub sha256: String,
+}
+
+pub trait AsBlobStore: Send + Sync {
+    /// Create temp blob `id` for an upload of `length` bytes, keeping bytes already written.
+    fn begin(&self, id: &str, length: u64) -> BlobFuture<()>;
+
+    /// Write `data` into temp blob `id` at `offset`, creating the temp blob if needed.
+    ///
+    /// Writes to disjoint ranges of one temp blob may run concurrently.
+    fn write_at(&self, id: &str, offset: u64, data: Vec<u8>) -> BlobFuture<()>;
+
+    /// Get the length of temp blob `id`, or `None` if it does not exist.
+    fn stat_temp(&self, id: &str) -> BlobFuture<Option<u64>>;
+
+    /// Compute the digests of temp blob `id`.
+    fn digest(&self, id: &str) -> BlobFuture<Digest>;
+
+    /// Move temp blob `id` to blob `key`.
+    ///
+    /// An existing blob is never overwritten. In that case the temp blob is dropped and `false`
+    /// is returned.
+    fn finalize(&self, id: &str, key: &str) -> BlobFuture<bool>;
+
+    /// Drop temp blob `id`.
+    fn abort(&self, id: &str) -> BlobFuture<()>;
+
+    /// List all temp blobs.
+    fn list_temp(&self) -> BlobFuture<Vec<TempInfo>>;
+
+    /// Open a reader over bytes `start..end` of blob `key`.
+    fn read_range(&self, key: &str, start: u64, end: u64) -> BlobFuture<BlobReader>;
+
+    /// Get the length of blob `key`, or `None` if it does not exist.
+    fn stat(&self, key: &str) -> BlobFuture<Option<u64>>;
+
+    /// Delete blob `key`.
+    fn delete(&self, key: &str) -> BlobFuture<()>;
+}
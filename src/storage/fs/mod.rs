@@ -0,0 +1,255 @@
+//! Blobs on the local filesystem.
+//!
+//! Blobs live in a sharded tree, `ab/cd/abcd…`, under the storage root, so that no single
+//! directory grows to millions of entries. Temp blobs live in `tmp/`.
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use crate::util;
+
+use sha2::{Digest as _, Sha256};
+
+use super::{AsBlobStore, BlobFuture, BlobReader, Digest, TempInfo};
+
+const TEMP_DIR: &str = "tmp";
+
+fn blob_path(root: &Path, key: &str) -> PathBuf {
+    if key.len() < 4 {
+        return root.join(key);
+    }
+    root.join(&key[0..2]).join(&key[2..4]).join(key)
+}
+
+fn temp_path(root: &Path, id: &str) -> PathBuf {
+    root.join(TEMP_DIR).join(format!("{id}.temp"))
+}
+
+// Public
+/// Move flat `<md5>` and `<md5>.temp` files in `root` into the sharded layout.
+pub fn migrate(root: &Path) -> io::Result<()> {
+    fs::create_dir_all(root.join(TEMP_DIR))?;
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let file_name = entry.file_name();
+        let file_name = match file_name.to_str() {
+            Some(r) => r,
+            None => continue,
+        };
+        let dest = if util::is_md5(file_name) {
+            blob_path(root, file_name)
+        } else {
+            match file_name.strip_suffix(".temp") {
+                Some(md5) if util::is_md5(md5) => temp_path(root, md5),
+                _ => continue,
+            }
+        };
+        if dest.exists() {
+            log::warn!("{} already exists\nwhen migrate", dest.display());
+            continue;
+        }
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(entry.path(), &dest)?;
+        log::info!("migrated {file_name} to {}", dest.display());
+    }
+    Ok(())
+}
+
+#[derive(Clone)]
+pub struct FsBlobStore {
+    root: Arc<PathBuf>,
+}
+
+impl FsBlobStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: Arc::new(root.into()),
+        }
+    }
+
+    pub(crate) fn temp_path(&self, id: &str) -> PathBuf {
+        temp_path(&self.root, id)
+    }
+}
+
+impl AsBlobStore for FsBlobStore {
+    fn begin(&self, id: &str, length: u64) -> BlobFuture<()> {
+        let path = self.temp_path(id);
+        Box::pin(async move {
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            let f = tokio::fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .open(&path)
+                .await?;
+            f.set_len(length).await
+        })
+    }
+
+    fn write_at(&self, id: &str, offset: u64, data: Vec<u8>) -> BlobFuture<()> {
+        let path = self.temp_path(id);
+        Box::pin(async move {
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            let mut f = tokio::fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .open(&path)
+                .await?;
+            f.seek(io::SeekFrom::Start(offset)).await?;
+            f.write_all(&data).await?;
+            f.flush().await
+        })
+    }
+
+    fn stat_temp(&self, id: &str) -> BlobFuture<Option<u64>> {
+        let path = self.temp_path(id);
+        Box::pin(async move {
+            Ok(util::not_found_as_none(tokio::fs::metadata(&path).await)?.map(|m| m.len()))
+        })
+    }
+
+    fn digest(&self, id: &str) -> BlobFuture<Digest> {
+        let path = self.temp_path(id);
+        Box::pin(async move {
+            let mut f = tokio::fs::File::open(&path).await?;
+            let mut md5_ctx = md5::Context::new();
+            let mut sha256_ctx = Sha256::new();
+            let mut buf = vec![0; 64 * 1024];
+            loop {
+                let n = f.read(&mut buf).await?;
+                if n == 0 {
+                    break;
+                }
+                md5_ctx.consume(&buf[..n]);
+                sha256_ctx.update(&buf[..n]);
+            }
+            Ok(Digest {
+                md5: format!("{:x}", md5_ctx.compute()),
+                sha256: format!("{:x}", sha256_ctx.finalize()),
+            })
+        })
+    }
+
+    fn finalize(&self, id: &str, key: &str) -> BlobFuture<bool> {
+        let temp_path = self.temp_path(id);
+        let path = blob_path(&self.root, key);
+        Box::pin(async move {
+            if tokio::fs::try_exists(&path).await? {
+                tokio::fs::remove_file(&temp_path).await?;
+                return Ok(false);
+            }
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::rename(&temp_path, &path).await?;
+            Ok(true)
+        })
+    }
+
+    fn abort(&self, id: &str) -> BlobFuture<()> {
+        let path = self.temp_path(id);
+        Box::pin(async move {
+            util::not_found_as_none(tokio::fs::remove_file(&path).await)?;
+            Ok(())
+        })
+    }
+
+    fn list_temp(&self) -> BlobFuture<Vec<TempInfo>> {
+        let dir = self.root.join(TEMP_DIR);
+        Box::pin(async move {
+            let mut temp_v = Vec::new();
+            let mut entries = match util::not_found_as_none(tokio::fs::read_dir(&dir).await)? {
+                Some(entries) => entries,
+                None => return Ok(temp_v),
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let file_name = entry.file_name();
+                let id = match file_name.to_str().and_then(|s| s.strip_suffix(".temp")) {
+                    Some(id) => id.to_string(),
+                    None => continue,
+                };
+                let metadata = entry.metadata().await?;
+                let modified_at = metadata
+                    .modified()?
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0);
+                temp_v.push(TempInfo {
+                    id,
+                    length: metadata.len(),
+                    modified_at,
+                });
+            }
+            Ok(temp_v)
+        })
+    }
+
+    fn read_range(&self, key: &str, start: u64, end: u64) -> BlobFuture<BlobReader> {
+        let path = blob_path(&self.root, key);
+        Box::pin(async move {
+            let mut f = tokio::fs::File::open(&path).await?;
+            f.seek(io::SeekFrom::Start(start)).await?;
+            let reader: BlobReader = Box::pin(f.take(end.saturating_sub(start)));
+            Ok(reader)
+        })
+    }
+
+    fn stat(&self, key: &str) -> BlobFuture<Option<u64>> {
+        let path = blob_path(&self.root, key);
+        Box::pin(async move {
+            Ok(util::not_found_as_none(tokio::fs::metadata(&path).await)?.map(|m| m.len()))
+        })
+    }
+
+    fn delete(&self, key: &str) -> BlobFuture<()> {
+        let path = blob_path(&self.root, key);
+        Box::pin(async move {
+            util::not_found_as_none(tokio::fs::remove_file(&path).await)?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{blob_path, migrate, temp_path};
+
+    #[test]
+    fn test_migrate() {
+        let root =
+            std::env::temp_dir().join(format!("pool_test_migrate_{}", rand::random::<u64>()));
+        fs::create_dir_all(&root).unwrap();
+        let md5 = format!("{:x}", md5::compute(b"hello"));
+        fs::write(root.join(&md5), b"hello").unwrap();
+        fs::write(root.join(format!("{md5}.temp")), b"hel").unwrap();
+        fs::write(root.join("config.toml"), b"").unwrap();
+
+        migrate(&root).unwrap();
+
+        assert_eq!(
+            blob_path(&root, &md5),
+            root.join(&md5[0..2]).join(&md5[2..4]).join(&md5)
+        );
+        assert_eq!(fs::read(blob_path(&root, &md5)).unwrap(), b"hello");
+        assert_eq!(fs::read(temp_path(&root, &md5)).unwrap(), b"hel");
+        assert!(root.join("config.toml").exists());
+        assert!(!root.join(&md5).exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
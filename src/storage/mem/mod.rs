@@ -0,0 +1,220 @@
+//! Blobs in memory, for tests.
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex},
+};
+
+use crate::util;
+
+use sha2::{Digest as _, Sha256};
+
+use super::{AsBlobStore, BlobFuture, BlobReader, Digest, TempInfo};
+
+#[derive(Default)]
+struct Inner {
+    temp_mp: HashMap<String, Vec<u8>>,
+    /// Declared lengths of temp blobs, which only grow as bytes are written.
+    temp_length_mp: HashMap<String, u64>,
+    temp_modified_mp: HashMap<String, u64>,
+    blob_mp: HashMap<String, Vec<u8>>,
+}
+
+fn not_found(name: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{name} not found"))
+}
+
+impl Inner {
+    fn temp_length(&self, id: &str, temp: &[u8]) -> u64 {
+        let length = self.temp_length_mp.get(id).copied().unwrap_or(0);
+        std::cmp::max(length, temp.len() as u64)
+    }
+
+    /// Fill temp blob `id` up to its declared length, once all of it is about to be read.
+    fn fill_temp(&mut self, id: &str) -> io::Result<&Vec<u8>> {
+        let length = self.temp_length_mp.get(id).copied().unwrap_or(0);
+        let temp = self.temp_mp.get_mut(id).ok_or_else(|| not_found(id))?;
+        if (temp.len() as u64) < length {
+            let length = usize::try_from(length).map_err(|_| {
+                io::Error::new(io::ErrorKind::OutOfMemory, format!("{id} too long"))
+            })?;
+            temp.resize(length, 0);
+        }
+        Ok(temp)
+    }
+}
+
+// Public
+#[derive(Clone, Default)]
+pub struct MemBlobStore {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl MemBlobStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl AsBlobStore for MemBlobStore {
+    fn begin(&self, id: &str, length: u64) -> BlobFuture<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let temp = inner.temp_mp.entry(id.to_string()).or_default();
+        temp.truncate(usize::try_from(length).unwrap_or(usize::MAX));
+        inner.temp_length_mp.insert(id.to_string(), length);
+        inner
+            .temp_modified_mp
+            .insert(id.to_string(), util::timestamp());
+        Box::pin(async { Ok(()) })
+    }
+
+    fn write_at(&self, id: &str, offset: u64, data: Vec<u8>) -> BlobFuture<()> {
+        let end = match usize::try_from(offset)
+            .ok()
+            .and_then(|offset| offset.checked_add(data.len()))
+        {
+            Some(end) => end,
+            None => {
+                let e = io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{offset} out of bound"),
+                );
+                return Box::pin(async { Err(e) });
+            }
+        };
+        let mut inner = self.inner.lock().unwrap();
+        let temp = inner.temp_mp.entry(id.to_string()).or_default();
+        if temp.len() < end {
+            temp.resize(end, 0);
+        }
+        temp[end - data.len()..end].copy_from_slice(&data);
+        inner
+            .temp_modified_mp
+            .insert(id.to_string(), util::timestamp());
+        Box::pin(async { Ok(()) })
+    }
+
+    fn stat_temp(&self, id: &str) -> BlobFuture<Option<u64>> {
+        let inner = self.inner.lock().unwrap();
+        let r = inner
+            .temp_mp
+            .get(id)
+            .map(|temp| inner.temp_length(id, temp));
+        Box::pin(async move { Ok(r) })
+    }
+
+    fn digest(&self, id: &str) -> BlobFuture<Digest> {
+        let r = self.inner.lock().unwrap().fill_temp(id).map(|temp| Digest {
+            md5: format!("{:x}", md5::compute(temp)),
+            sha256: format!("{:x}", Sha256::digest(temp)),
+        });
+        Box::pin(async { r })
+    }
+
+    fn finalize(&self, id: &str, key: &str) -> BlobFuture<bool> {
+        let mut inner = self.inner.lock().unwrap();
+        let r = inner.fill_temp(id).map(|_| ());
+        inner.temp_modified_mp.remove(id);
+        inner.temp_length_mp.remove(id);
+        let r = match (r, inner.temp_mp.remove(id)) {
+            (Ok(()), Some(temp)) => {
+                if inner.blob_mp.contains_key(key) {
+                    Ok(false)
+                } else {
+                    inner.blob_mp.insert(key.to_string(), temp);
+                    Ok(true)
+                }
+            }
+            (Err(e), _) => Err(e),
+            (_, None) => Err(not_found(id)),
+        };
+        Box::pin(async { r })
+    }
+
+    fn abort(&self, id: &str) -> BlobFuture<()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.temp_mp.remove(id);
+        inner.temp_length_mp.remove(id);
+        inner.temp_modified_mp.remove(id);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn list_temp(&self) -> BlobFuture<Vec<TempInfo>> {
+        let inner = self.inner.lock().unwrap();
+        let temp_v = inner
+            .temp_mp
+            .iter()
+            .map(|(id, temp)| TempInfo {
+                id: id.clone(),
+                length: inner.temp_length(id, temp),
+                modified_at: inner.temp_modified_mp.get(id).copied().unwrap_or(0),
+            })
+            .collect();
+        Box::pin(async { Ok(temp_v) })
+    }
+
+    fn read_range(&self, key: &str, start: u64, end: u64) -> BlobFuture<BlobReader> {
+        let r = match self.inner.lock().unwrap().blob_mp.get(key) {
+            Some(blob) => match blob.get(start as usize..end as usize) {
+                Some(slice) => {
+                    let reader: BlobReader = Box::pin(io::Cursor::new(slice.to_vec()));
+                    Ok(reader)
+                }
+                None => Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("{start}..{end} out of bound"),
+                )),
+            },
+            None => Err(not_found(key)),
+        };
+        Box::pin(async { r })
+    }
+
+    fn stat(&self, key: &str) -> BlobFuture<Option<u64>> {
+        let r = self
+            .inner
+            .lock()
+            .unwrap()
+            .blob_mp
+            .get(key)
+            .map(|blob| blob.len() as u64);
+        Box::pin(async move { Ok(r) })
+    }
+
+    fn delete(&self, key: &str) -> BlobFuture<()> {
+        self.inner.lock().unwrap().blob_mp.remove(key);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use crate::storage::AsBlobStore;
+
+    use super::MemBlobStore;
+
+    #[tokio::test]
+    async fn test_lazy() {
+        let store = MemBlobStore::new();
+        // Declaring a huge upload allocates nothing.
+        store.begin("a", u64::MAX).await.unwrap();
+        assert_eq!(store.stat_temp("a").await.unwrap(), Some(u64::MAX));
+        assert_eq!(
+            store
+                .write_at("a", u64::MAX, b"x".to_vec())
+                .await
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidInput
+        );
+        store.abort("a").await.unwrap();
+
+        store.begin("b", 5).await.unwrap();
+        store.write_at("b", 1, b"ell".to_vec()).await.unwrap();
+        assert_eq!(store.stat_temp("b").await.unwrap(), Some(5));
+        let digest = store.digest("b").await.unwrap();
+        assert_eq!(digest.md5, format!("{:x}", md5::compute(b"\0ell\0")));
+    }
+}
@@ -0,0 +1,169 @@
+//! Users in the edge graph.
+//!
+//! Every user is a `root->user` node. Passwords are stored as Argon2 PHC strings, hex-encoded
+//! because the PHC format contains `$`, which edge scripts treat as a variable. Users are indexed
+//! by email under `root->user_by_email`.
+use std::{io, sync::Arc};
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use edge_lib::{data::AsDataManager, Path};
+
+use crate::{
+    graph::{self, get_field, Journal},
+    util,
+};
+
+/// Argon2 PHC string with the default parameters, verified against when there is no user, so
+/// that an unknown email takes as long as a wrong password.
+const DUMMY_PASSWORD_HASH: &str =
+    "$argon2id$v=19$m=19456,t=2,p=1$wYcc4/Z08ujJemStdHB8cQ$iY007AmLP2MbdiJocL8php8tZ1x2ztwEtcRMcbzruac";
+
+// Public
+#[derive(Debug, Clone)]
+pub struct UserRecord {
+    pub email: String,
+    /// Argon2 PHC string.
+    pub password_hash: String,
+    pub created_at: u64,
+}
+
+impl UserRecord {
+    /// Record a user created now, hashing `password` with a random salt.
+    pub fn new(email: &str, password: &str) -> io::Result<Self> {
+        Ok(Self {
+            email: email.to_string(),
+            password_hash: hash_password(password)?,
+            created_at: util::timestamp(),
+        })
+    }
+
+    /// Load the record of user node `node`.
+    pub async fn load(dm: &dyn AsDataManager, node: &str) -> io::Result<Self> {
+        let password_hash = util::hex2byte_v(&get_field(dm, node, "password_hash").await?);
+        Ok(Self {
+            email: util::unescape(&get_field(dm, node, "email").await?),
+            password_hash: String::from_utf8(password_hash).map_err(io::Error::other)?,
+            created_at: get_field(dm, node, "created_at")
+                .await?
+                .parse()
+                .unwrap_or(0),
+        })
+    }
+
+    pub fn verify(&self, password: &str) -> bool {
+        verify_password(&self.password_hash, password)
+    }
+
+    /// Write this record into the graph as a new `root->user` node.
+    pub async fn insert(&self, dm: Arc<dyn AsDataManager>, journal: &Journal) -> io::Result<()> {
+        let script = self.script(&graph::new_node());
+        graph::execute(dm, journal, script, "insert user").await
+    }
+
+    /// Script writing this record as `root->user` node `node`, with its index.
+    fn script(&self, node: &str) -> String {
+        [
+            &format!("$->$user = = {node} _"),
+            &format!("$->$user->email = = {} _", util::escape(&self.email)),
+            &format!(
+                "$->$user->password_hash = = {} _",
+                util::byte_v2hex(self.password_hash.as_bytes())
+            ),
+            &format!("$->$user->created_at = = {} _", self.created_at),
+            "root->user += = $->$user _",
+            &graph::index_line_v("user_by_email", &self.email, "user").join("\n"),
+        ]
+        .join("\n")
+    }
+}
+
+/// Find the user with `email`.
+pub async fn find(dm: Arc<dyn AsDataManager>, email: &str) -> io::Result<Option<UserRecord>> {
+    match graph::lookup(dm.as_ref(), "user_by_email", email)
+        .await?
+        .first()
+    {
+        Some(node) => Ok(Some(UserRecord::load(dm.as_ref(), node).await?)),
+        None => Ok(None),
+    }
+}
+
+/// Scripts recreating every user record on its node.
+pub async fn snapshot(dm: Arc<dyn AsDataManager>) -> io::Result<Vec<String>> {
+    let mut script_v = Vec::new();
+    for node in dm.get(&Path::from_str("root->user")).await? {
+        script_v.push(UserRecord::load(dm.as_ref(), &node).await?.script(&node));
+    }
+    Ok(script_v)
+}
+
+/// Verify `password` of `record`, taking as long when there is no such user.
+pub fn verify_user(record: Option<&UserRecord>, password: &str) -> bool {
+    match record {
+        Some(record) => record.verify(password),
+        None => {
+            verify_password(DUMMY_PASSWORD_HASH, password);
+            false
+        }
+    }
+}
+
+/// Hash `password` with Argon2id and a random salt into a PHC string.
+pub fn hash_password(password: &str) -> io::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| io::Error::other(e.to_string()))?
+        .to_string())
+}
+
+pub fn verify_password(password_hash: &str, password: &str) -> bool {
+    match PasswordHash::new(password_hash) {
+        Ok(password_hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &password_hash)
+            .is_ok(),
+        Err(e) => {
+            log::warn!("{e}\nwhen verify_password");
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use edge_lib::data::{AsDataManager, MemDataManager};
+
+    use crate::graph::Journal;
+
+    use super::{find, hash_password, verify_password, verify_user, UserRecord};
+
+    #[test]
+    fn test_password() {
+        let password_hash = hash_password("password").unwrap();
+        assert!(password_hash.starts_with("$argon2id$"));
+        assert_ne!(password_hash, hash_password("password").unwrap());
+        assert!(verify_password(&password_hash, "password"));
+        assert!(!verify_password(&password_hash, "Password"));
+        assert!(!verify_password("", "password"));
+        assert!(!verify_user(None, "password"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test() {
+        let dm = MemDataManager::new();
+        let journal = Journal::new();
+        assert!(find(dm.divide(), "a@b.c").await.unwrap().is_none());
+        UserRecord::new("a@b.c", "password")
+            .unwrap()
+            .insert(dm.divide(), &journal)
+            .await
+            .unwrap();
+
+        let record = find(dm.divide(), "a@b.c").await.unwrap().unwrap();
+        assert!(record.verify("password"));
+        assert!(find(dm.divide(), "b@b.c").await.unwrap().is_none());
+    }
+}
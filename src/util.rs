@@ -1,6 +1,7 @@
 use std::io;
 
 use edge_lib::ScriptTree;
+use rand::Rng;
 
 pub mod native {
     use pnet::datalink;
@@ -60,6 +61,59 @@ pub async fn http_execute1(uri: &str, script_tree: &ScriptTree) -> io::Result<St
     })
 }
 
+/// Map a `NotFound` error to `None`.
+pub fn not_found_as_none<T>(r: io::Result<T>) -> io::Result<Option<T>> {
+    match r {
+        Ok(r) => Ok(Some(r)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Seconds since the unix epoch.
+pub fn timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("can not get timestamp")
+        .as_secs()
+}
+
+/// Whether `s` is a lowercase hex md5 digest.
+pub fn is_md5(s: &str) -> bool {
+    s.len() == 32 && s.chars().all(|ch| matches!(ch, '0'..='9' | 'a'..='f'))
+}
+
+/// Whether `s` is a lowercase hex sha256 digest.
+pub fn is_sha256(s: &str) -> bool {
+    s.len() == 64 && s.chars().all(|ch| matches!(ch, '0'..='9' | 'a'..='f'))
+}
+
+const ESCAPE_SET: &percent_encoding::AsciiSet = &percent_encoding::NON_ALPHANUMERIC
+    .remove(b'.')
+    .remove(b'-')
+    .remove(b'@');
+
+/// Percent-encode `s` so that it can be used as a value in an edge script.
+pub fn escape(s: &str) -> String {
+    percent_encoding::utf8_percent_encode(s, ESCAPE_SET).to_string()
+}
+
+/// Reverse `escape`.
+pub fn unescape(s: &str) -> String {
+    percent_encoding::percent_decode_str(s)
+        .decode_utf8_lossy()
+        .to_string()
+}
+
+/// A random alphanumeric string, short enough for a URL and safe in edge scripts.
+pub fn slug(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
 const NUM_2_HEXCHAR: [char; 16] = [
     '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'a', 'b', 'c', 'd', 'e', 'f',
 ];